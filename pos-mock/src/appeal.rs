@@ -1,4 +1,5 @@
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::db::appeal::AppealStatus;
 use crate::db::AppealRepo;
@@ -11,28 +12,59 @@ use uuid::Uuid;
 pub struct AppealService {
     repo: AppealRepo,
     popularity_map: DashMap<uuid::Uuid, (Instant, Duration)>,
+    popularity_loaded: AtomicBool,
 }
 
 const BASE_APPEAL_DIFF_TIME: usize = 0;
 
 impl AppealService {
     pub async fn new(repo: AppealRepo) -> Self {
-        let popularity_map = Self::calculate_popularity_map(&repo).await;
-
-        Self {
+        let service = Self {
             repo,
-            popularity_map,
+            popularity_map: DashMap::new(),
+            popularity_loaded: AtomicBool::new(false),
+        };
+
+        // retried by the readiness check when the database is not reachable yet
+        if let Err(e) = service.load_popularity_map().await {
+            tracing::error!(error = %e, "failed to load appeals popularity");
+        }
+
+        service
+    }
+
+    /// Database is reachable and the popularity map is loaded
+    pub async fn ready(&self) -> Result<(), crate::Error> {
+        self.repo.ping().await?;
+
+        if !self.popularity_loaded.load(Ordering::Acquire) {
+            self.load_popularity_map().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn load_popularity_map(&self) -> Result<(), crate::Error> {
+        let popularity_map = Self::calculate_popularity_map(&self.repo).await?;
+        for (client_id, value) in popularity_map {
+            self.popularity_map.insert(client_id, value);
         }
+        self.popularity_loaded.store(true, Ordering::Release);
+
+        Ok(())
     }
 
     async fn calculate_popularity_map(
         repo: &AppealRepo,
-    ) -> DashMap<uuid::Uuid, (Instant, Duration)> {
-        let stat = repo.get_appeals_stat().await.unwrap();
-        let max = stat.iter().map(|(_, a)| *a).max().unwrap();
+    ) -> Result<DashMap<uuid::Uuid, (Instant, Duration)>, crate::Error> {
+        let stat = repo.get_appeals_stat().await?;
+        let Some(max) = stat.iter().map(|(_, a)| *a).max() else {
+            return Ok(DashMap::new());
+        };
 
         let now = Instant::now();
-        stat.into_iter()
+        Ok(stat
+            .into_iter()
             .map(|(uuid, amount)| {
                 let uuid = uuid::Uuid::from_str(&uuid).expect("valid uuid");
                 let coef = max as f32 / amount as f32;
@@ -42,7 +74,7 @@ impl AppealService {
 
                 (uuid, (now, message_duration))
             })
-            .collect::<DashMap<_, _>>()
+            .collect::<DashMap<_, _>>())
     }
 
     pub async fn next_appeal(&self, client_id: Uuid) -> Option<Appeal> {
@@ -10,6 +10,13 @@ impl AppealRepo {
         Self { pool }
     }
 
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("select 1")
+            .execute(&*self.pool)
+            .await
+            .map(|_| ())
+    }
+
     pub async fn get_pending_appeal(&self, client_id: String) -> Result<Appeal> {
         sqlx::query_as!(
             Appeal,
@@ -43,7 +50,8 @@ impl AppealRepo {
 
         let mut records = Vec::new();
 
-        while let Some(Ok(record)) = s.next().await {
+        while let Some(record) = s.next().await {
+            let record = record?;
             records.push((record.client_id.unwrap(), record.amount.unwrap() as usize));
         }
 
@@ -7,7 +7,8 @@ pub enum Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            _ => write!(f, "error"),
+            Error::Database(e) => write!(f, "database error: {e}"),
+            Error::Serde(e) => write!(f, "serialization error: {e}"),
         }
     }
 }
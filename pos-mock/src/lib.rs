@@ -14,18 +14,27 @@ type Result<T> = std::result::Result<T, Error>;
 
 pub struct PosMock {
     service: AppealService,
+    schemas: Files,
 }
 
 type Files = Vec<std::path::PathBuf>;
 impl PosMock {
     pub async fn new(db: &str) -> Self {
-        let pg = sqlx::PgPool::connect(db).await.unwrap();
+        // connected on the first query, so the mock starts before the database
+        let pg = sqlx::PgPool::connect_lazy(db).unwrap();
         let repo = db::AppealRepo::new(std::sync::Arc::new(pg));
         Self {
             service: AppealService::new(repo).await,
+            schemas: Vec::new(),
         }
     }
 
+    /// POS EDMS schemas the requests are validated against
+    pub fn with_schemas(mut self, schemas: Files) -> Self {
+        self.schemas = schemas;
+        self
+    }
+
     async fn handle_appeal_list(
         &self,
         request: types::AppealListRequest,
@@ -70,4 +79,12 @@ impl Service for PosMock {
             files: files.unwrap_or_default(),
         })
     }
+
+    fn schemas(&self) -> Files {
+        self.schemas.clone()
+    }
+
+    async fn ready(&self) -> Result<()> {
+        self.service.ready().await
+    }
 }
@@ -0,0 +1,8 @@
+fn main() {
+    // libxml2 is only linked for the xsd validation
+    #[cfg(feature = "xsd")]
+    pkg_config::Config::new()
+        .atleast_version("2.9")
+        .probe("libxml-2.0")
+        .expect("libxml2 >= 2.9 is required by the `xsd` feature");
+}
@@ -0,0 +1,67 @@
+//! Pause between the retries of a failed `accept`, shared by the listeners
+
+use std::{io, time::Duration};
+
+/// Bounds of the pause after a failed accept, e.g. when out of file descriptors
+const MIN_ACCEPT_DELAY: Duration = Duration::from_millis(5);
+const MAX_ACCEPT_DELAY: Duration = Duration::from_secs(1);
+
+/// Retrying at once only spins, the pause doubles until an accept succeeds
+#[derive(Default)]
+pub(crate) struct AcceptBackoff {
+    delay: Duration,
+}
+
+impl AcceptBackoff {
+    /// The next failure starts over from the shortest pause
+    pub fn reset(&mut self) {
+        self.delay = Duration::ZERO;
+    }
+
+    /// Pause before the next accept, `None` if only the connection failed
+    /// and the listener itself is fine
+    pub fn next(&mut self, e: &io::Error) -> Option<Duration> {
+        if matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionReset
+        ) {
+            return None;
+        }
+
+        self.delay = (self.delay * 2).clamp(MIN_ACCEPT_DELAY, MAX_ACCEPT_DELAY);
+        tracing::warn!(
+            error = %e,
+            delay_ms = self.delay.as_millis() as u64,
+            "failed to accept connection"
+        );
+        Some(self.delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AcceptBackoff;
+
+    use std::{io, time::Duration};
+
+    #[test]
+    pub fn test_backoff() {
+        let mut backoff = AcceptBackoff::default();
+        let emfile = || io::Error::from_raw_os_error(24);
+
+        assert_eq!(backoff.next(&emfile()), Some(Duration::from_millis(5)));
+        assert_eq!(backoff.next(&emfile()), Some(Duration::from_millis(10)));
+        for _ in 0..10 {
+            backoff.next(&emfile());
+        }
+        assert_eq!(backoff.next(&emfile()), Some(Duration::from_secs(1)));
+
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(backoff.next(&reset), None);
+
+        backoff.reset();
+        assert_eq!(backoff.next(&emfile()), Some(Duration::from_millis(5)));
+    }
+}
@@ -0,0 +1,414 @@
+//! Typed client of the adapter REST API, requires the `client` feature
+//!
+//! ```no_run
+//! # async fn run(entrypoint_id: uuid::Uuid) -> Result<(), rsmev::client::Error> {
+//! # #[derive(serde::Serialize)] struct Request;
+//! # #[derive(serde::Deserialize)] struct Response;
+//! let client = rsmev::client::RsmevClient::new("http://localhost:8080", entrypoint_id)
+//!     .with_node_id("node-1");
+//!
+//! client.send(&Request, Vec::new()).await?;
+//! let response = client.wait_response(std::time::Duration::from_secs(30)).await?;
+//! let content: Response = response.content()?;
+//! client.confirm(response.request_id).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{io, sync::Arc, time::Duration};
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::api::{
+    ConfirmResponse, ErrorResponse, GetResponse, SendRequest, SendResponse, StatusResponse,
+};
+use crate::body::{self, Body, EncodedXml, File};
+use crate::file_store::FileStore;
+use crate::{DEFAULT_NODE_ID_HEADER, DEFAULT_PATH_PREFIX};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    /// Request rejected by the adapter
+    Api(StatusCode, ErrorResponse),
+    /// Unexpected response, e.g. from a proxy in front of the adapter
+    UnexpectedResponse(StatusCode, String),
+    Xml(body::Error),
+    /// No response arrived within the timeout
+    Timeout,
+    /// Attachments without an http url are read from a file store, see
+    /// `RsmevClient::with_file_store`
+    NoFileStore,
+    File(String, io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "http request failed: {e}"),
+            Error::Api(status, e) => write!(f, "{status} {}: {}", e.code, e.message),
+            Error::UnexpectedResponse(status, body) => {
+                write!(f, "unexpected response {status}: {body}")
+            }
+            Error::Xml(e) => e.fmt(f),
+            Error::Timeout => write!(f, "no response within the timeout"),
+            Error::NoFileStore => write!(f, "file store is not configured"),
+            Error::File(url, e) => write!(f, "failed to read file `{url}`: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Error::Http(value)
+    }
+}
+
+impl From<body::Error> for Error {
+    fn from(value: body::Error) -> Self {
+        Error::Xml(value)
+    }
+}
+
+/// Client of a single entrypoint of the mock or the real adapter
+///
+/// Attachments with an http url are downloaded, the other urls, e.g. the ftp
+/// paths of the real adapter, are read from a file store over a mounted directory
+#[derive(Clone)]
+pub struct RsmevClient {
+    http: reqwest::Client,
+    base_url: String,
+    path_prefix: String,
+    entrypoint_id: Uuid,
+    node_id: Option<String>,
+    node_id_header: String,
+    api_key: Option<String>,
+    files: Option<Arc<dyn FileStore>>,
+    poll_interval: Duration,
+}
+
+impl RsmevClient {
+    /// `base_url` is the scheme and the host of the adapter, e.g. `http://localhost:8080`
+    pub fn new(base_url: impl Into<String>, entrypoint_id: Uuid) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into(),
+            path_prefix: DEFAULT_PATH_PREFIX.to_string(),
+            entrypoint_id,
+            node_id: None,
+            node_id_header: DEFAULT_NODE_ID_HEADER.to_string(),
+            api_key: None,
+            files: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Use a configured http client, e.g. with client certificates
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefix = prefix.into();
+        self
+    }
+
+    /// Node the requests are sent from, the default node of the adapter otherwise
+    pub fn with_node_id(mut self, node_id: impl Into<String>) -> Self {
+        self.node_id = Some(node_id.into());
+        self
+    }
+
+    pub fn with_node_id_header(mut self, name: impl Into<String>) -> Self {
+        self.node_id_header = name.into();
+        self
+    }
+
+    /// Sent as `Authorization: Bearer <key>`
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Store the attachments are read from unless their url is http
+    pub fn with_file_store(mut self, files: impl FileStore) -> Self {
+        self.files = Some(Arc::new(files));
+        self
+    }
+
+    /// Interval between the `getresponse` calls of `wait_response`
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Send a value serialized to xml, returns the request id
+    pub async fn send<T: Serialize>(&self, content: &T, files: Vec<File>) -> Result<Uuid, Error> {
+        let body = Body {
+            xml: EncodedXml::serialize(content)?,
+            files,
+        };
+
+        self.send_body(body).await
+    }
+
+    pub async fn send_body(&self, body: Body) -> Result<Uuid, Error> {
+        let response = self
+            .post("sendrequest")
+            .json(&SendRequest { body })
+            .send()
+            .await?;
+        let response: SendResponse = parse(response).await?;
+
+        Ok(response.request_id)
+    }
+
+    /// Take the next response of the node, `None` if there is none yet
+    pub async fn get_response(&self) -> Result<Option<GetResponse>, Error> {
+        let response = self.post("getresponse").send().await?;
+
+        // an empty queue is reported as 404 with the `null` body
+        if response.status() == StatusCode::NOT_FOUND {
+            let text = response.text().await?;
+            if text.trim().is_empty() || text.trim() == "null" {
+                return Ok(None);
+            }
+
+            return Err(api_error(StatusCode::NOT_FOUND, text));
+        }
+
+        parse(response).await
+    }
+
+    /// Poll `getresponse` until a response arrives
+    pub async fn wait_response(&self, timeout: Duration) -> Result<GetResponse, Error> {
+        let poll = async {
+            loop {
+                if let Some(response) = self.get_response().await? {
+                    return Ok(response);
+                }
+
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        };
+
+        tokio::time::timeout(timeout, poll)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Confirm the response, otherwise it is redelivered after the TTL.
+    /// Confirming it again succeeds as well
+    pub async fn confirm(&self, request_id: Uuid) -> Result<(), Error> {
+        let response = self
+            .post(&format!("confirmprocessing/{request_id}"))
+            .send()
+            .await?;
+        let _: ConfirmResponse = parse(response).await?;
+
+        Ok(())
+    }
+
+    /// Stage of the request, the endpoint is served by the mock only
+    pub async fn status(&self, request_id: Uuid) -> Result<StatusResponse, Error> {
+        let response = self
+            .request(Method::GET, &format!("status/{request_id}"))
+            .send()
+            .await?;
+
+        parse(response).await
+    }
+
+    /// Download an attachment of the response, an http url is fetched with
+    /// the http client, any other url is read from the file store
+    pub async fn download(&self, file: &File) -> Result<Vec<u8>, Error> {
+        if file.url.starts_with("http://") || file.url.starts_with("https://") {
+            let response = self.http.get(&file.url).send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(Error::UnexpectedResponse(status, response.text().await?));
+            }
+
+            return Ok(response.bytes().await?.to_vec());
+        }
+
+        let files = self.files.as_ref().ok_or(Error::NoFileStore)?;
+
+        files
+            .read(&file.url)
+            .map_err(|e| Error::File(file.url.clone(), e))
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = format!(
+            "{}{}/{}/{path}",
+            self.base_url.trim_end_matches('/'),
+            self.path_prefix.trim_end_matches('/'),
+            self.entrypoint_id,
+        );
+
+        let mut request = self.http.request(method, url);
+        if let Some(node_id) = &self.node_id {
+            request = request.header(self.node_id_header.as_str(), node_id);
+        }
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        request
+    }
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    let status = response.status();
+    let text = response.text().await?;
+
+    if !status.is_success() {
+        return Err(api_error(status, text));
+    }
+
+    serde_json::from_str(&text).map_err(|_| Error::UnexpectedResponse(status, text))
+}
+
+fn api_error(status: StatusCode, text: String) -> Error {
+    match serde_json::from_str(&text) {
+        Ok(e) => Error::Api(status, e),
+        Err(_) => Error::UnexpectedResponse(status, text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, RsmevClient};
+    use crate::api::RequestStatus;
+    use crate::body::File;
+    use crate::file_store::MemoryFileStore;
+    use crate::service::{Message, Service};
+    use crate::{Entrypoint, RsmevServer};
+
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct Echo {
+        #[serde(rename = "Text")]
+        text: String,
+    }
+
+    /// Echoes the text with the prefix
+    struct EchoService(&'static str);
+
+    impl Service for EchoService {
+        type Request = Echo;
+        type Response = Echo;
+        type Error = std::io::Error;
+
+        async fn handle(&self, content: Message<Echo>) -> Result<Message<Echo>, Self::Error> {
+            Ok(Message {
+                content: Echo {
+                    text: format!("{}{}", self.0, content.content.text),
+                },
+                files: Vec::new(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_round_trip() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .entrypoint(Entrypoint::new(entrypoint_id, "echo"))
+            .file_store(MemoryFileStore::new())
+            .queue_ttl(Duration::from_millis(50))
+            .build(EchoService(""))
+            .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server.serve(listener));
+
+        let client = RsmevClient::new(format!("http://{addr}"), entrypoint_id)
+            .with_node_id("node-1")
+            .with_poll_interval(Duration::from_millis(10));
+
+        assert!(client.get_response().await.unwrap().is_none());
+
+        let text = "hello".to_string();
+        let request_id = client.send(&Echo { text }, Vec::new()).await.unwrap();
+
+        let response = client.wait_response(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.request_id, request_id);
+        let status = client.status(request_id).await.unwrap();
+        assert_eq!(status.status, RequestStatus::Delivered);
+        assert_eq!(status.node_id, "node-1");
+        assert_eq!(response.content::<Echo>().unwrap().text, "hello");
+        assert_eq!(response.delivery_attempt, Some(1));
+
+        // a redelivery is the same message
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let redelivery = client.get_response().await.unwrap().unwrap();
+        assert_eq!(redelivery.message_id, response.message_id);
+        assert_eq!(redelivery.rec_id, response.rec_id);
+        assert_ne!(redelivery.rec_id, request_id);
+        assert_eq!(redelivery.delivery_attempt, Some(2));
+        assert_eq!(
+            redelivery.first_delivered_at_ms,
+            response.first_delivered_at_ms
+        );
+
+        client.confirm(request_id).await.unwrap();
+        let status = client.status(request_id).await.unwrap();
+        assert_eq!(status.status, RequestStatus::Confirmed);
+        assert!(!status.fault);
+        assert!(matches!(
+            client.status(Uuid::new_v4()).await,
+            Err(Error::Api(_, e)) if e.code == "UNKNOWN_REQUEST"
+        ));
+        // a repeated confirmation succeeds
+        client.confirm(request_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_download() {
+        let routes =
+            axum::Router::new().route("/files/a.txt", axum::routing::get(|| async { "remote" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, routes).await });
+
+        let files = MemoryFileStore::new();
+        let url = "/stored/b.txt".to_string();
+        files.insert(url.clone(), "local");
+
+        let client = RsmevClient::new(format!("http://{addr}"), Uuid::new_v4());
+        let file = |url: String| File {
+            name: "a.txt".to_string(),
+            url,
+            signature: None,
+        };
+
+        let remote = file(format!("http://{addr}/files/a.txt"));
+        assert_eq!(client.download(&remote).await.unwrap(), b"remote");
+        assert!(matches!(
+            client.download(&file(format!("http://{addr}/files/missing.txt"))).await,
+            Err(Error::UnexpectedResponse(status, _)) if status == 404
+        ));
+        assert!(matches!(
+            client.download(&file(url.clone())).await,
+            Err(Error::NoFileStore)
+        ));
+
+        let client = client.with_file_store(files);
+        assert_eq!(client.download(&file(url)).await.unwrap(), b"local");
+    }
+}
@@ -1,7 +1,11 @@
-use std::{collections::VecDeque, time::Instant};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant, SystemTime},
+};
 
 pub trait KeyGenerator {
-    type Key: Clone + PartialEq<Self::Key>;
+    type Key: Clone + Eq + Hash;
 
     fn generate() -> Self::Key;
 }
@@ -15,36 +19,136 @@ impl KeyGenerator for UuidKey {
     }
 }
 
-pub struct QueueItem<K, V> {
-    key: K,
+pub struct QueueItem<V> {
     value: V,
+    added: Instant,
     taken: Option<Instant>,
+    first_taken: Option<SystemTime>,
+    deliveries: u32,
 }
 
-impl<K, V> QueueItem<K, V> {
-    pub fn new(key: K, value: V) -> Self {
+impl<V> QueueItem<V> {
+    pub fn new(value: V) -> Self {
         Self {
-            key,
             value,
+            added: Instant::now(),
             taken: None,
+            first_taken: None,
+            deliveries: 0,
         }
     }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Time since the value was added
+    pub fn age(&self) -> Duration {
+        self.added.elapsed()
+    }
+
+    /// Taken and waiting for the confirmation
+    pub fn is_taken(&self) -> bool {
+        self.taken.is_some()
+    }
+
+    /// Times the value was taken
+    pub fn deliveries(&self) -> u32 {
+        self.deliveries
+    }
+
+    /// Wall clock time of the first take
+    pub fn first_taken(&self) -> Option<SystemTime> {
+        self.first_taken
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Not confirmed values, taken ones included
+    pub len: usize,
+    pub in_flight: usize,
+    pub oldest_age: Option<Duration>,
+    /// Values taken again after the TTL expired, since the queue was created
+    pub redeliveries: u64,
+    pub dead_letters: usize,
+}
+
+/// Number of the confirmed keys remembered to detect a repeated confirmation
+const CONFIRMED_HISTORY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    Confirmed,
+    AlreadyConfirmed,
+    /// Moved to the dead-letter queue, it cannot be confirmed anymore
+    DeadLetter,
+    Unknown,
 }
 
-// TODO: two VecDeque(one for not taken, one for taken)
-pub struct ConfirmQueue<T, const TTL: u64, KG: KeyGenerator = UuidKey> {
-    container: VecDeque<QueueItem<KG::Key, T>>,
+/// Queue of the values waiting for a confirmation.
+///
+/// A taken value is redelivered when it is not confirmed within the TTL,
+/// after `max_deliveries` it is moved to the dead-letter queue instead.
+/// The deques only hold keys, keys of confirmed or re-taken items are
+/// skipped lazily, so every operation, `stats` included, is amortized O(1).
+pub struct ConfirmQueue<T, KG: KeyGenerator = UuidKey> {
+    ttl: Duration,
+    items: HashMap<KG::Key, QueueItem<T>>,
+    // not taken keys, the most recent is taken first
+    ready: VecDeque<KG::Key>,
+    // taken keys ordered by the take time, so the first one expires first
+    in_flight: VecDeque<(Instant, KG::Key)>,
+    // number of the taken items, the deque above also holds stale keys
+    taken: usize,
+    // keys in the order they were added, the first one is always in `items`
+    added: VecDeque<(Instant, KG::Key)>,
+    // recently confirmed keys, the oldest are forgotten first
+    confirmed: HashSet<KG::Key>,
+    confirmed_order: VecDeque<KG::Key>,
+    redeliveries: u64,
+    max_deliveries: Option<u32>,
+    dead_letters: HashMap<KG::Key, QueueItem<T>>,
+    // keys moved to the dead-letter queue since the last `take_dead_lettered`
+    dead_lettered: Vec<KG::Key>,
 }
 
-impl<T, const TTL: u64, KG: KeyGenerator> ConfirmQueue<T, TTL, KG> {
-    pub const fn new() -> Self {
+impl<T, KG: KeyGenerator> ConfirmQueue<T, KG> {
+    pub fn new(ttl: Duration) -> Self {
         Self {
-            container: VecDeque::new(),
+            ttl,
+            items: HashMap::new(),
+            ready: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            taken: 0,
+            added: VecDeque::new(),
+            confirmed: HashSet::new(),
+            confirmed_order: VecDeque::new(),
+            redeliveries: 0,
+            max_deliveries: None,
+            dead_letters: HashMap::new(),
+            dead_lettered: Vec::new(),
         }
     }
 
+    /// Move a value to the dead-letter queue once it was taken `max` times
+    /// without a confirmation, it is redelivered forever otherwise
+    pub fn with_max_deliveries(mut self, max: Option<u32>) -> Self {
+        self.max_deliveries = max;
+        self
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
     pub fn add_with_key(&mut self, key: KG::Key, value: T) {
-        self.container.push_back(QueueItem::new(key, value));
+        let item = QueueItem::new(value);
+        self.added.push_back((item.added, key.clone()));
+        if let Some(replaced) = self.items.insert(key.clone(), item) {
+            self.forget(&replaced);
+        }
+        self.ready.push_back(key);
     }
 
     #[allow(unused)]
@@ -54,36 +158,203 @@ impl<T, const TTL: u64, KG: KeyGenerator> ConfirmQueue<T, TTL, KG> {
         key
     }
 
+    /// Take a not taken value, or an expired one to redeliver it
     pub fn take(&mut self) -> Option<(&KG::Key, &T)> {
-        let v = self.container.back()?;
-        let mut qi = match v.taken {
-            None => self.container.pop_back(),
-            Some(ttl) if ttl.elapsed() >= std::time::Duration::from_millis(TTL) => {
-                self.container.pop_back()
+        self.take_item().map(|(key, item)| (key, &item.value))
+    }
+
+    /// Same as `take`, with the delivery details of the value
+    pub fn take_item(&mut self) -> Option<(&KG::Key, &QueueItem<T>)> {
+        let now = Instant::now();
+        let key = self.next_ready().or_else(|| self.next_expired(now))?;
+
+        let item = self.items.get_mut(&key)?;
+        if item.taken.is_none() {
+            self.taken += 1;
+        }
+        item.taken = Some(now);
+        item.first_taken.get_or_insert_with(SystemTime::now);
+        item.deliveries += 1;
+        self.in_flight.push_back((now, key));
+
+        let (_, key) = self.in_flight.back()?;
+        Some((key, self.items.get(key)?))
+    }
+
+    pub fn confirm(&mut self, key: &KG::Key) -> Confirmation {
+        let Some(item) = self.items.remove(key) else {
+            return if self.confirmed.contains(key) {
+                Confirmation::AlreadyConfirmed
+            } else if self.dead_letters.contains_key(key) {
+                Confirmation::DeadLetter
+            } else {
+                Confirmation::Unknown
+            };
+        };
+        self.forget(&item);
+
+        if self.confirmed_order.len() >= CONFIRMED_HISTORY {
+            if let Some(oldest) = self.confirmed_order.pop_front() {
+                self.confirmed.remove(&oldest);
+            }
+        }
+        self.confirmed.insert(key.clone());
+        self.confirmed_order.push_back(key.clone());
+
+        Confirmation::Confirmed
+    }
+
+    pub fn get(&self, key: &KG::Key) -> Option<&QueueItem<T>> {
+        self.items.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&KG::Key, &QueueItem<T>)> {
+        self.items.iter()
+    }
+
+    /// Drop the value without confirming it
+    pub fn remove(&mut self, key: &KG::Key) -> Option<T> {
+        let item = self.items.remove(key)?;
+        self.forget(&item);
+        Some(item.value)
+    }
+
+    /// Make the value the next one to take, even if it is taken
+    pub fn redeliver(&mut self, key: &KG::Key) -> bool {
+        let Some(item) = self.items.get_mut(key) else {
+            return false;
+        };
+
+        if item.taken.take().is_some() {
+            self.taken -= 1;
+        }
+        self.ready.push_back(key.clone());
+        true
+    }
+
+    /// Put a value straight to the dead-letter queue, e.g. on restore
+    pub fn add_dead_letter(&mut self, key: KG::Key, value: T) {
+        self.dead_letters.insert(key, QueueItem::new(value));
+    }
+
+    pub fn dead_letters(&self) -> impl Iterator<Item = (&KG::Key, &QueueItem<T>)> {
+        self.dead_letters.iter()
+    }
+
+    pub fn get_dead_letter(&self, key: &KG::Key) -> Option<&QueueItem<T>> {
+        self.dead_letters.get(key)
+    }
+
+    pub fn remove_dead_letter(&mut self, key: &KG::Key) -> Option<T> {
+        self.dead_letters.remove(key).map(|item| item.value)
+    }
+
+    /// Move the value from the dead-letter queue back to the queue, its
+    /// deliveries are counted from zero
+    pub fn requeue_dead_letter(&mut self, key: &KG::Key) -> bool {
+        let Some(item) = self.dead_letters.remove(key) else {
+            return false;
+        };
+
+        self.add_with_key(key.clone(), item.value);
+        true
+    }
+
+    /// Keys moved to the dead-letter queue since the previous call
+    pub fn take_dead_lettered(&mut self) -> Vec<KG::Key> {
+        std::mem::take(&mut self.dead_lettered)
+    }
+
+    /// Items taken again after the TTL, since the queue was created
+    pub fn redeliveries(&self) -> u64 {
+        self.redeliveries
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            len: self.items.len(),
+            in_flight: self.taken,
+            oldest_age: self.added.front().map(|(added, _)| added.elapsed()),
+            redeliveries: self.redeliveries,
+            dead_letters: self.dead_letters.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Update the counters after the item is removed from `items`
+    fn forget(&mut self, item: &QueueItem<T>) {
+        if item.is_taken() {
+            self.taken -= 1;
+        }
+
+        // stale keys are dropped up to the oldest item still in the queue
+        while let Some((added, key)) = self.added.front() {
+            if self.items.get(key).is_some_and(|i| i.added == *added) {
+                break;
             }
-            _ => None,
-        }?;
+            self.added.pop_front();
+        }
+    }
 
-        qi.taken = Some(Instant::now());
-        self.container.push_front(qi);
+    fn next_ready(&mut self) -> Option<KG::Key> {
+        while let Some(key) = self.ready.pop_back() {
+            if self.items.get(&key).is_some_and(|i| i.taken.is_none()) {
+                return Some(key);
+            }
+        }
 
-        let new_qi = self.container.front().unwrap();
-        Some((&new_qi.key, &new_qi.value))
+        None
     }
 
-    pub fn confirm(&mut self, key: &KG::Key) {
-        let idx = self.container.iter().position(|q| q.key == *key).unwrap();
-        self.container.remove(idx);
+    fn next_expired(&mut self, now: Instant) -> Option<KG::Key> {
+        while let Some((taken, key)) = self.in_flight.front() {
+            // stale entry of a confirmed or already redelivered item
+            if self.items.get(key).and_then(|i| i.taken) != Some(*taken) {
+                self.in_flight.pop_front();
+                continue;
+            }
+
+            if now.duration_since(*taken) < self.ttl {
+                return None;
+            }
+
+            let (_, key) = self.in_flight.pop_front()?;
+            let deliveries = self.items.get(&key).map_or(0, QueueItem::deliveries);
+            if self.max_deliveries.is_some_and(|max| deliveries >= max) {
+                if let Some(mut item) = self.items.remove(&key) {
+                    self.forget(&item);
+                    item.taken = None;
+                    self.dead_letters.insert(key.clone(), item);
+                    self.dead_lettered.push(key);
+                }
+                continue;
+            }
+
+            self.redeliveries += 1;
+            return Some(key);
+        }
+
+        None
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ConfirmQueue;
+    use super::{ConfirmQueue, Confirmation};
+    use std::time::Duration;
+
+    const TTL: Duration = Duration::from_millis(10);
 
     #[test]
     pub fn test_fifo() {
-        let mut queue = ConfirmQueue::<String, 10>::new();
+        let mut queue = ConfirmQueue::<String>::new(TTL);
 
         let _ = queue.add("random".to_string());
         let _ = queue.add("string".to_string());
@@ -93,13 +364,13 @@ mod tests {
 
     #[test]
     pub fn test_not_confirm() {
-        let mut queue = ConfirmQueue::<String, 10>::new();
+        let mut queue = ConfirmQueue::<String>::new(TTL);
 
         let _ = queue.add("random".to_string());
         let _ = queue.add("string".to_string());
 
         let _ = queue.take().unwrap();
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::thread::sleep(TTL);
 
         let (_, v2) = queue.take().unwrap();
         assert_eq!("random", v2);
@@ -108,8 +379,9 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     pub fn test_confirm() {
-        let mut queue = ConfirmQueue::<String, 10>::new();
+        let mut queue = ConfirmQueue::<String>::new(TTL);
 
         let _ = queue.add("random".to_string());
         let _ = queue.add("string".to_string());
@@ -118,10 +390,124 @@ mod tests {
             let (k1, _) = queue.take().unwrap();
             k1.clone()
         };
-        queue.confirm(&k1);
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(queue.confirm(&k1), Confirmation::Confirmed);
+        assert_eq!(queue.confirm(&k1), Confirmation::AlreadyConfirmed);
+        assert_eq!(queue.confirm(&uuid::Uuid::new_v4()), Confirmation::Unknown);
+        std::thread::sleep(TTL);
 
         let (_, v2) = queue.take().unwrap();
         assert_eq!("random", v2);
     }
+
+    #[test]
+    pub fn test_redeliver_oldest() {
+        let mut queue = ConfirmQueue::<String>::new(TTL);
+
+        let _ = queue.add("first".to_string());
+        let _ = queue.add("second".to_string());
+
+        let second = *queue.take().unwrap().0;
+        let _ = queue.take().unwrap();
+        assert!(queue.take().is_none());
+
+        let _ = queue.confirm(&second);
+        std::thread::sleep(TTL);
+
+        assert_eq!("first", queue.take().unwrap().1);
+        assert!(queue.take().is_none());
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.stats().redeliveries, 1);
+        assert_eq!(queue.redeliveries(), 1);
+    }
+
+    #[test]
+    pub fn test_dead_letter() {
+        let mut queue = ConfirmQueue::<String>::new(TTL).with_max_deliveries(Some(2));
+
+        let key = queue.add("poison".to_string());
+        for deliveries in 1..=2 {
+            let _ = queue.take().unwrap();
+            assert_eq!(queue.get(&key).unwrap().deliveries(), deliveries);
+            std::thread::sleep(TTL);
+        }
+
+        assert!(queue.take().is_none());
+        assert_eq!(queue.take_dead_lettered(), vec![key]);
+        assert!(queue.take_dead_lettered().is_empty());
+        assert_eq!(queue.stats().dead_letters, 1);
+        assert_eq!(queue.confirm(&key), Confirmation::DeadLetter);
+
+        assert!(queue.requeue_dead_letter(&key));
+        assert_eq!(queue.stats().dead_letters, 0);
+        assert_eq!(*queue.take().unwrap().0, key);
+        assert_eq!(queue.get(&key).unwrap().deliveries(), 1);
+    }
+
+    #[test]
+    pub fn test_take_item() {
+        let mut queue = ConfirmQueue::<String>::new(TTL);
+        let _ = queue.add("value".to_string());
+
+        let first_taken = {
+            let (_, item) = queue.take_item().unwrap();
+            assert_eq!(item.deliveries(), 1);
+            item.first_taken().unwrap()
+        };
+        std::thread::sleep(TTL);
+
+        let (_, item) = queue.take_item().unwrap();
+        assert_eq!(item.deliveries(), 2);
+        assert_eq!(item.first_taken(), Some(first_taken));
+    }
+
+    #[test]
+    pub fn test_stats() {
+        let mut queue = ConfirmQueue::<String>::new(TTL).with_max_deliveries(Some(1));
+
+        let first = queue.add("first".to_string());
+        std::thread::sleep(TTL);
+        let second = queue.add("second".to_string());
+        let third = queue.add("third".to_string());
+        assert!(queue.stats().oldest_age.unwrap() >= TTL);
+
+        while queue.take().is_some() {}
+        assert_eq!(queue.stats().in_flight, 3);
+
+        // the oldest age moves to the next item once the first one is gone
+        assert_eq!(queue.confirm(&first), Confirmation::Confirmed);
+        assert!(queue.stats().oldest_age.unwrap() < TTL);
+        assert_eq!(queue.stats().in_flight, 2);
+
+        assert!(queue.redeliver(&second));
+        assert!(queue.remove(&second).is_some());
+        assert_eq!(queue.stats().in_flight, 1);
+
+        std::thread::sleep(TTL);
+        assert!(queue.take().is_none());
+        assert_eq!(queue.take_dead_lettered(), vec![third]);
+
+        let stats = queue.stats();
+        assert_eq!((stats.len, stats.in_flight, stats.dead_letters), (0, 0, 1));
+        assert_eq!(stats.oldest_age, None);
+    }
+
+    #[test]
+    pub fn test_redeliver_and_remove() {
+        let mut queue = ConfirmQueue::<String>::new(TTL);
+
+        let first = queue.add("first".to_string());
+        let second = queue.add("second".to_string());
+
+        assert_eq!(*queue.take().unwrap().0, second);
+        assert_eq!(queue.stats().in_flight, 1);
+
+        assert!(queue.redeliver(&second));
+        assert_eq!(queue.stats().in_flight, 0);
+        assert_eq!(*queue.take().unwrap().0, second);
+
+        assert_eq!(queue.remove(&first).as_deref(), Some("first"));
+        assert!(!queue.redeliver(&first));
+        assert!(queue.take().is_none());
+        assert_eq!(queue.stats().len, 1);
+    }
 }
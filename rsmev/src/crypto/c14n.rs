@@ -0,0 +1,204 @@
+//! Exclusive XML canonicalization without comments
+//! (`http://www.w3.org/2001/10/xml-exc-c14n#`), without `InclusiveNamespaces`.
+
+use std::collections::BTreeMap;
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+/// Namespace declarations, prefix (empty for the default namespace) to uri
+pub(crate) type Namespaces = BTreeMap<String, String>;
+
+/// Canonicalize `xml`, `inherited` are the namespaces declared by the
+/// ancestors of the fragment
+pub(crate) fn canonicalize(xml: &str, inherited: &Namespaces) -> Result<String, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.expand_empty_elements(true);
+
+    let mut output = String::with_capacity(xml.len());
+    // in scope and rendered namespaces of the open elements
+    let mut scopes = vec![(inherited.clone(), Namespaces::new())];
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let (scope, rendered) = scopes.last().cloned().unwrap_or_default();
+                let (scope, rendered) = start_tag(&e, scope, rendered, &mut output)?;
+                scopes.push((scope, rendered));
+            }
+            Event::End(e) => {
+                scopes.pop();
+                output.push_str("</");
+                output.push_str(&String::from_utf8_lossy(e.name().as_ref()));
+                output.push('>');
+            }
+            Event::Text(e) if scopes.len() > 1 => escape_text(&e.unescape()?, &mut output),
+            Event::CData(e) if scopes.len() > 1 => {
+                escape_text(&String::from_utf8_lossy(&e.into_inner()), &mut output)
+            }
+            Event::PI(e) if scopes.len() > 1 => {
+                output.push_str("<?");
+                output.push_str(&String::from_utf8_lossy(&e));
+                output.push_str("?>");
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(output)
+}
+
+/// Namespace declarations of the element, merged into `scope`
+pub(crate) fn declare_namespaces(
+    e: &BytesStart,
+    mut scope: Namespaces,
+) -> Result<Namespaces, quick_xml::Error> {
+    for attr in e.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).to_string();
+
+        if key == "xmlns" {
+            scope.insert(String::new(), attr.unescape_value()?.to_string());
+        } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+            scope.insert(prefix.to_string(), attr.unescape_value()?.to_string());
+        }
+    }
+
+    Ok(scope)
+}
+
+fn prefix(qname: &str) -> &str {
+    qname.split_once(':').map(|(p, _)| p).unwrap_or_default()
+}
+
+fn start_tag(
+    e: &BytesStart,
+    scope: Namespaces,
+    mut rendered: Namespaces,
+    output: &mut String,
+) -> Result<(Namespaces, Namespaces), quick_xml::Error> {
+    let scope = declare_namespaces(e, scope)?;
+    let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+
+    let mut attributes = Vec::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).to_string();
+
+        if key != "xmlns" && !key.starts_with("xmlns:") {
+            attributes.push((key, attr.unescape_value()?.to_string()));
+        }
+    }
+
+    // visibly utilized namespaces: the element prefix and prefixes of the attributes
+    let mut utilized = vec![prefix(&name).to_string()];
+    utilized.extend(
+        attributes
+            .iter()
+            .map(|(key, _)| prefix(key).to_string())
+            .filter(|p| !p.is_empty()),
+    );
+
+    let mut declarations = Namespaces::new();
+    for prefix in utilized {
+        let uri = scope.get(&prefix).cloned().unwrap_or_default();
+        let rendered_uri = rendered.get(&prefix).cloned().unwrap_or_default();
+
+        if uri != rendered_uri {
+            declarations.insert(prefix.clone(), uri.clone());
+            rendered.insert(prefix, uri);
+        }
+    }
+
+    // attributes are ordered by namespace uri, then by local name
+    attributes.sort_by_cached_key(|(key, _)| {
+        let (uri, local) = match key.split_once(':') {
+            Some((p, local)) => (scope.get(p).cloned().unwrap_or_default(), local.to_string()),
+            None => (String::new(), key.clone()),
+        };
+        (uri, local)
+    });
+
+    output.push('<');
+    output.push_str(&name);
+    for (prefix, uri) in &declarations {
+        if prefix.is_empty() {
+            output.push_str(" xmlns=\"");
+        } else {
+            output.push_str(" xmlns:");
+            output.push_str(prefix);
+            output.push_str("=\"");
+        }
+        escape_attribute(uri, output);
+        output.push('"');
+    }
+    for (key, value) in &attributes {
+        output.push(' ');
+        output.push_str(key);
+        output.push_str("=\"");
+        escape_attribute(value, output);
+        output.push('"');
+    }
+    output.push('>');
+
+    Ok((scope, rendered))
+}
+
+fn escape_text(text: &str, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '\r' => output.push_str("&#xD;"),
+            c => output.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, output: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '"' => output.push_str("&quot;"),
+            '\t' => output.push_str("&#x9;"),
+            '\n' => output.push_str("&#xA;"),
+            '\r' => output.push_str("&#xD;"),
+            c => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonicalize, Namespaces};
+
+    #[test]
+    pub fn test_canonicalize() {
+        let xml = r#"<?xml version="1.0"?>
+<a:Root xmlns:a="urn:a" xmlns:b="urn:b" z="1" a:y='2'><Empty/><b:Child>x &amp; y<![CDATA[<z>]]></b:Child><!-- comment --><c xmlns="urn:d" q="&quot;v&#10;"/></a:Root>
+"#;
+
+        assert_eq!(
+            canonicalize(xml, &Namespaces::new()).unwrap(),
+            "<a:Root xmlns:a=\"urn:a\" z=\"1\" a:y=\"2\"><Empty></Empty>\
+             <b:Child xmlns:b=\"urn:b\">x &amp; y&lt;z&gt;</b:Child>\
+             <c xmlns=\"urn:d\" q=\"&quot;v&#xA;\"></c></a:Root>"
+        );
+    }
+
+    #[test]
+    pub fn test_canonicalize_inherited() {
+        let inherited = Namespaces::from([("ds".to_string(), "urn:ds".to_string())]);
+
+        assert_eq!(
+            canonicalize("<ds:SignedInfo><ds:Reference URI=\"\"/></ds:SignedInfo>", &inherited)
+                .unwrap(),
+            "<ds:SignedInfo xmlns:ds=\"urn:ds\"><ds:Reference URI=\"\"></ds:Reference></ds:SignedInfo>"
+        );
+    }
+}
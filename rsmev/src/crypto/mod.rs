@@ -0,0 +1,137 @@
+//! Test cryptography for the exchanged documents, requires the `crypto` feature.
+//!
+//! Keys and certificates are expected to be local test ones, nothing here is
+//! meant to protect real data.
+
+mod c14n;
+pub mod pkcs7;
+pub mod xmldsig;
+
+use std::{io, path::Path};
+
+use openssl::{
+    error::ErrorStack,
+    pkey::{PKey, Private},
+    x509::{store::X509StoreBuilder, X509},
+};
+
+/// Signing and verification of the exchanged documents, everything is disabled by default
+#[derive(Default)]
+pub struct CryptoConfig {
+    /// verify `signaturePKCS7` of the inbound attachments
+    pub attachment_verifier: Option<pkcs7::Pkcs7Verifier>,
+    /// fill `signaturePKCS7` of the outbound attachments
+    pub attachment_signer: Option<pkcs7::Pkcs7Signer>,
+    /// verify enveloped signature of the inbound xml
+    pub xml_verifier: Option<xmldsig::XmlVerifier>,
+    /// sign the outbound xml
+    pub xml_signer: Option<xmldsig::XmlSigner>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    OpenSsl(ErrorStack),
+    Xml(quick_xml::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {e}"),
+            Error::OpenSsl(e) => write!(f, "openssl error: {e}"),
+            Error::Xml(e) => write!(f, "xml error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(value: quick_xml::Error) -> Self {
+        Error::Xml(value)
+    }
+}
+
+impl From<ErrorStack> for Error {
+    fn from(value: ErrorStack) -> Self {
+        Error::OpenSsl(value)
+    }
+}
+
+/// What to do with a request which signature failed the verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignaturePolicy {
+    /// reject the request with an error
+    #[default]
+    Reject,
+    /// log a warning and pass the request to the service
+    Warn,
+}
+
+/// Read PEM or DER certificates from a file
+pub fn read_certificates(path: &Path) -> Result<Vec<X509>, Error> {
+    let content = std::fs::read(path)?;
+
+    match X509::stack_from_pem(&content) {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        _ => Ok(vec![X509::from_der(&content)?]),
+    }
+}
+
+/// Read PEM or DER private key from a file
+pub fn read_private_key(path: &Path) -> Result<PKey<Private>, Error> {
+    let content = std::fs::read(path)?;
+
+    match PKey::private_key_from_pem(&content) {
+        Ok(key) => Ok(key),
+        Err(_) => Ok(PKey::private_key_from_der(&content)?),
+    }
+}
+
+/// Read the first certificate of `cert` and the private key of a signer
+pub(crate) fn read_identity(cert: &Path, key: &Path) -> Result<(X509, PKey<Private>), Error> {
+    let cert = read_certificates(cert)?.into_iter().next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificate in `{}`", cert.display()),
+        )
+    })?;
+
+    Ok((cert, read_private_key(key)?))
+}
+
+/// Build a trust store from every certificate file in the directory
+pub(crate) fn trust_store(dir: &Path) -> Result<openssl::x509::store::X509Store, Error> {
+    let mut store = X509StoreBuilder::new()?;
+    for cert in dir_certificates(dir)? {
+        store.add_cert(cert)?;
+    }
+
+    Ok(store.build())
+}
+
+/// Certificates of every file in the directory, other files are skipped
+pub(crate) fn dir_certificates(dir: &Path) -> Result<Vec<X509>, Error> {
+    let mut certs = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        match read_certificates(&path) {
+            Ok(file_certs) => certs.extend(file_certs),
+            Err(e) => tracing::warn!(file = %path.display(), error = %e, "skip certificate"),
+        }
+    }
+
+    Ok(certs)
+}
@@ -0,0 +1,208 @@
+//! Detached PKCS#7 signatures of the attachments (`signaturePKCS7` of the file)
+
+use std::path::Path;
+
+use base64::prelude::*;
+use openssl::{
+    error::ErrorStack,
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    pkey::{PKey, Private},
+    stack::Stack,
+    x509::{store::X509Store, X509},
+};
+
+use super::{read_identity, trust_store, Error, SignaturePolicy};
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Base64(base64::DecodeError),
+    Malformed(ErrorStack),
+    Invalid(ErrorStack),
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "signature is missing"),
+            SignatureError::Base64(e) => write!(f, "signature is not valid base64: {e}"),
+            SignatureError::Malformed(e) => write!(f, "signature is not a PKCS#7 structure: {e}"),
+            SignatureError::Invalid(e) => write!(f, "signature verification failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Verifies attachment signatures against the trusted certificates
+pub struct Pkcs7Verifier {
+    store: X509Store,
+    policy: SignaturePolicy,
+}
+
+impl Pkcs7Verifier {
+    pub fn new(store: X509Store, policy: SignaturePolicy) -> Self {
+        Self { store, policy }
+    }
+
+    /// Trust every certificate (PEM or DER) found in `dir`
+    pub fn from_dir(dir: impl AsRef<Path>, policy: SignaturePolicy) -> Result<Self, Error> {
+        Ok(Self::new(trust_store(dir.as_ref())?, policy))
+    }
+
+    pub fn policy(&self) -> SignaturePolicy {
+        self.policy
+    }
+
+    /// Verify base64 (DER) or PEM encoded detached `signature` of the `content`
+    pub fn verify(&self, signature: Option<&str>, content: &[u8]) -> Result<(), SignatureError> {
+        let signature = signature
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .ok_or(SignatureError::Missing)?;
+
+        let pkcs7 = if signature.starts_with("-----BEGIN") {
+            Pkcs7::from_pem(signature.as_bytes())
+        } else {
+            let der = BASE64_STANDARD
+                .decode(signature)
+                .map_err(SignatureError::Base64)?;
+            Pkcs7::from_der(&der)
+        }
+        .map_err(SignatureError::Malformed)?;
+
+        let certs = Stack::new().map_err(SignatureError::Malformed)?;
+        pkcs7
+            .verify(
+                &certs,
+                &self.store,
+                Some(content),
+                None,
+                Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY,
+            )
+            .map_err(SignatureError::Invalid)
+    }
+}
+
+/// Produces detached signatures of the outbound attachments
+pub struct Pkcs7Signer {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+impl Pkcs7Signer {
+    pub fn new(cert: X509, key: PKey<Private>) -> Self {
+        Self { cert, key }
+    }
+
+    /// Load the signing key and the certificate of the signer, PEM or DER
+    pub fn from_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, Error> {
+        let (cert, key) = read_identity(cert.as_ref(), key.as_ref())?;
+
+        Ok(Self::new(cert, key))
+    }
+
+    /// Base64 encoded DER of the detached signature, as expected in `signaturePKCS7`
+    pub fn sign(&self, content: &[u8]) -> Result<String, Error> {
+        let certs = Stack::<X509>::new()?;
+        let signature = Pkcs7::sign(
+            &self.cert,
+            &self.key,
+            &certs,
+            content,
+            Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY,
+        )?;
+
+        Ok(BASE64_STANDARD.encode(signature.to_der()?))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Pkcs7Signer, Pkcs7Verifier, SignatureError};
+    use crate::crypto::SignaturePolicy;
+
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        x509::{store::X509StoreBuilder, X509Builder, X509NameBuilder, X509},
+    };
+
+    /// Self-signed test certificate
+    pub(crate) fn test_identity() -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "rsmev test").unwrap();
+        let name = name.build();
+
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (cert.build(), key)
+    }
+
+    fn verifier(cert: X509) -> Pkcs7Verifier {
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(cert).unwrap();
+
+        Pkcs7Verifier::new(store.build(), SignaturePolicy::Reject)
+    }
+
+    #[test]
+    pub fn test_sign_and_verify() {
+        let (cert, key) = test_identity();
+        let content = b"attachment";
+
+        let signature = Pkcs7Signer::new(cert.clone(), key).sign(content).unwrap();
+
+        let verifier = verifier(cert);
+        assert!(verifier.verify(Some(&signature), content).is_ok());
+        assert!(matches!(
+            verifier.verify(Some(&signature), b"tampered"),
+            Err(SignatureError::Invalid(_))
+        ));
+        assert!(matches!(
+            verifier.verify(None, content),
+            Err(SignatureError::Missing)
+        ));
+
+        let (untrusted, _) = test_identity();
+        assert!(matches!(
+            self::verifier(untrusted).verify(Some(&signature), content),
+            Err(SignatureError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_from_files() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (cert, key) = test_identity();
+        let (cert_path, key_path) = (dir.join("cert.der"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.to_der().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        assert!(Pkcs7Signer::from_files(&cert_path, &key_path).is_ok());
+
+        // a file without a certificate is an error, not a panic
+        std::fs::write(&cert_path, b"").unwrap();
+        assert!(Pkcs7Signer::from_files(&cert_path, &key_path).is_err());
+        assert!(Pkcs7Signer::from_files(&key_path, &key_path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
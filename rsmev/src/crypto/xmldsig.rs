@@ -0,0 +1,423 @@
+//! Enveloped XML digital signature of the whole document (`Reference URI=""`),
+//! exclusive canonicalization, RSA-SHA256
+//!
+//! The `Signature` element is appended as the last child of the root element.
+
+use std::path::Path;
+
+use base64::prelude::*;
+use openssl::{
+    hash::{hash, MessageDigest},
+    pkey::{PKey, Private},
+    sign::{Signer, Verifier},
+    stack::Stack,
+    x509::{store::X509Store, X509StoreContext, X509},
+};
+use quick_xml::{events::Event, Reader};
+
+use super::{
+    c14n::{self, Namespaces},
+    read_identity, trust_store, Error, SignaturePolicy,
+};
+
+pub const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+#[derive(Debug)]
+pub enum XmlSignatureError {
+    Missing,
+    Malformed(String),
+    UnsupportedAlgorithm(String),
+    DigestMismatch,
+    UntrustedCertificate(String),
+    Invalid,
+}
+
+impl std::fmt::Display for XmlSignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XmlSignatureError::Missing => write!(f, "xml signature is missing"),
+            XmlSignatureError::Malformed(e) => write!(f, "xml signature is malformed: {e}"),
+            XmlSignatureError::UnsupportedAlgorithm(a) => {
+                write!(f, "xml signature algorithm `{a}` is not supported")
+            }
+            XmlSignatureError::DigestMismatch => write!(f, "xml digest does not match"),
+            XmlSignatureError::UntrustedCertificate(e) => {
+                write!(f, "xml signature certificate is not trusted: {e}")
+            }
+            XmlSignatureError::Invalid => write!(f, "xml signature verification failed"),
+        }
+    }
+}
+
+impl std::error::Error for XmlSignatureError {}
+
+impl From<quick_xml::Error> for XmlSignatureError {
+    fn from(value: quick_xml::Error) -> Self {
+        XmlSignatureError::Malformed(value.to_string())
+    }
+}
+
+impl From<openssl::error::ErrorStack> for XmlSignatureError {
+    fn from(value: openssl::error::ErrorStack) -> Self {
+        XmlSignatureError::Malformed(value.to_string())
+    }
+}
+
+/// Signs the serialized response documents
+pub struct XmlSigner {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+impl XmlSigner {
+    /// `key` is expected to be an RSA key
+    pub fn new(cert: X509, key: PKey<Private>) -> Self {
+        Self { cert, key }
+    }
+
+    /// Load the signing key and the certificate embedded into `KeyInfo`, PEM or DER
+    pub fn from_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, Error> {
+        let (cert, key) = read_identity(cert.as_ref(), key.as_ref())?;
+
+        Ok(Self::new(cert, key))
+    }
+
+    /// Append an enveloped `Signature` to the document
+    pub fn sign(&self, xml: &str) -> Result<String, Error> {
+        let canonical = c14n::canonicalize(xml, &Namespaces::new())?;
+        let digest = BASE64_STANDARD.encode(hash(MessageDigest::sha256(), canonical.as_bytes())?);
+
+        let signed_info = format!(
+            "<ds:SignedInfo>\
+             <ds:CanonicalizationMethod Algorithm=\"{EXC_C14N}\"/>\
+             <ds:SignatureMethod Algorithm=\"{RSA_SHA256}\"/>\
+             <ds:Reference URI=\"\">\
+             <ds:Transforms>\
+             <ds:Transform Algorithm=\"{ENVELOPED}\"/>\
+             <ds:Transform Algorithm=\"{EXC_C14N}\"/>\
+             </ds:Transforms>\
+             <ds:DigestMethod Algorithm=\"{SHA256}\"/>\
+             <ds:DigestValue>{digest}</ds:DigestValue>\
+             </ds:Reference>\
+             </ds:SignedInfo>"
+        );
+        let inherited = Namespaces::from([("ds".to_string(), DSIG_NS.to_string())]);
+        let canonical = c14n::canonicalize(&signed_info, &inherited)?;
+
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.update(canonical.as_bytes())?;
+        let signature_value = BASE64_STANDARD.encode(signer.sign_to_vec()?);
+        let certificate = BASE64_STANDARD.encode(self.cert.to_der()?);
+
+        let signature = format!(
+            "<ds:Signature xmlns:ds=\"{DSIG_NS}\">\
+             {signed_info}\
+             <ds:SignatureValue>{signature_value}</ds:SignatureValue>\
+             <ds:KeyInfo><ds:X509Data><ds:X509Certificate>{certificate}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>\
+             </ds:Signature>"
+        );
+
+        insert_into_root(xml, &signature)
+    }
+}
+
+/// Insert `content` as the last child of the root element
+fn insert_into_root(xml: &str, content: &str) -> Result<String, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut depth = 0usize;
+
+    loop {
+        let position = reader.buffer_position();
+
+        match reader.read_event()? {
+            Event::Start(_) => depth += 1,
+            Event::End(_) if depth == 1 => {
+                return Ok(format!("{}{content}{}", &xml[..position], &xml[position..]));
+            }
+            Event::End(_) => depth -= 1,
+            Event::Empty(e) if depth == 0 => {
+                let end = reader.buffer_position();
+                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                let tag = xml[position..end].trim_end_matches("/>");
+
+                return Ok(format!(
+                    "{}{tag}>{content}</{name}>{}",
+                    &xml[..position],
+                    &xml[end..]
+                ));
+            }
+            Event::Eof => {
+                return Err(quick_xml::Error::UnexpectedEof("root element".to_string()).into())
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parts of the enveloped signature found in the document
+#[derive(Default)]
+struct SignatureParts {
+    /// byte range of the `Signature` element
+    signature: Option<(usize, usize)>,
+    /// byte range of the `SignedInfo` element and namespaces in scope
+    signed_info: Option<(usize, usize, Namespaces)>,
+    reference_uri: Option<String>,
+    algorithms: Vec<String>,
+    digest_value: String,
+    signature_value: String,
+    certificate: String,
+}
+
+fn find_signature(xml: &str) -> Result<SignatureParts, XmlSignatureError> {
+    let mut reader = Reader::from_str(xml);
+    let mut parts = SignatureParts::default();
+    let mut scopes = vec![Namespaces::new()];
+    // element names inside of the signature
+    let mut path: Vec<String> = Vec::new();
+    let mut signature_start = None;
+    let mut signed_info_start = None;
+
+    loop {
+        let position = reader.buffer_position();
+
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let scope =
+                    c14n::declare_namespaces(&e, scopes.last().cloned().unwrap_or_default())?;
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                let prefix = e
+                    .name()
+                    .prefix()
+                    .map(|p| String::from_utf8_lossy(p.as_ref()).to_string())
+                    .unwrap_or_default();
+
+                let is_dsig = scope.get(&prefix).map(String::as_str) == Some(DSIG_NS);
+                if signature_start.is_none()
+                    && parts.signature.is_none()
+                    && scopes.len() == 2
+                    && is_dsig
+                    && name == "Signature"
+                {
+                    signature_start = Some(position);
+                }
+                if signature_start.is_some() {
+                    if name == "SignedInfo" {
+                        signed_info_start =
+                            Some((position, scopes.last().cloned().unwrap_or_default()));
+                    }
+                    collect_attributes(&e, &name, &mut parts)?;
+                    path.push(name);
+                }
+
+                scopes.push(scope);
+            }
+            Event::Empty(e) if signature_start.is_some() => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                collect_attributes(&e, &name, &mut parts)?;
+            }
+            Event::Text(e) if signature_start.is_some() => {
+                let text = e.unescape()?;
+                match path.last().map(String::as_str) {
+                    Some("DigestValue") => parts.digest_value.push_str(&text),
+                    Some("SignatureValue") => parts.signature_value.push_str(&text),
+                    Some("X509Certificate") => parts.certificate.push_str(&text),
+                    _ => {}
+                }
+            }
+            Event::End(_) => {
+                scopes.pop();
+
+                if let Some(start) = signature_start {
+                    let end = reader.buffer_position();
+                    match path.pop().as_deref() {
+                        Some("SignedInfo") => {
+                            if let Some((si_start, scope)) = signed_info_start.take() {
+                                parts.signed_info = Some((si_start, end, scope));
+                            }
+                        }
+                        Some("Signature") if path.is_empty() => {
+                            parts.signature = Some((start, end));
+                            signature_start = None;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(parts)
+}
+
+fn collect_attributes(
+    e: &quick_xml::events::BytesStart,
+    name: &str,
+    parts: &mut SignatureParts,
+) -> Result<(), XmlSignatureError> {
+    for attr in e.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        let value = attr.unescape_value()?.to_string();
+
+        match (name, attr.key.as_ref()) {
+            ("Reference", b"URI") => parts.reference_uri = Some(value),
+            (_, b"Algorithm") => parts.algorithms.push(value),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the enveloped `Signature` element, the document is returned as is
+/// when it is not signed
+pub fn strip_signature(xml: &str) -> String {
+    match find_signature(xml) {
+        Ok(SignatureParts {
+            signature: Some((start, end)),
+            ..
+        }) => format!("{}{}", &xml[..start], &xml[end..]),
+        _ => xml.to_string(),
+    }
+}
+
+/// Verifies enveloped signatures of the inbound documents
+pub struct XmlVerifier {
+    store: Option<X509Store>,
+    policy: SignaturePolicy,
+}
+
+impl XmlVerifier {
+    /// Without a trust store any certificate from `KeyInfo` is accepted
+    pub fn new(store: Option<X509Store>, policy: SignaturePolicy) -> Self {
+        Self { store, policy }
+    }
+
+    /// Trust every certificate (PEM or DER) found in `dir`
+    pub fn from_dir(dir: impl AsRef<Path>, policy: SignaturePolicy) -> Result<Self, Error> {
+        Ok(Self::new(Some(trust_store(dir.as_ref())?), policy))
+    }
+
+    pub fn policy(&self) -> SignaturePolicy {
+        self.policy
+    }
+
+    /// Verify the signature and return the document without it
+    pub fn verify(&self, xml: &str) -> Result<String, XmlSignatureError> {
+        let parts = find_signature(xml)?;
+        let (start, end) = parts.signature.ok_or(XmlSignatureError::Missing)?;
+        let (si_start, si_end, scope) = parts
+            .signed_info
+            .ok_or_else(|| XmlSignatureError::Malformed("`SignedInfo` is missing".to_string()))?;
+
+        if parts.reference_uri.as_deref() != Some("") {
+            return Err(XmlSignatureError::Malformed(
+                "only `Reference URI=\"\"` is supported".to_string(),
+            ));
+        }
+        if let Some(algorithm) = parts
+            .algorithms
+            .iter()
+            .find(|a| ![EXC_C14N, ENVELOPED, RSA_SHA256, SHA256].contains(&a.as_str()))
+        {
+            return Err(XmlSignatureError::UnsupportedAlgorithm(algorithm.clone()));
+        }
+
+        let unsigned = format!("{}{}", &xml[..start], &xml[end..]);
+        let canonical = c14n::canonicalize(&unsigned, &Namespaces::new())?;
+        let digest = hash(MessageDigest::sha256(), canonical.as_bytes())?;
+        if decode(&parts.digest_value)? != digest.as_ref() {
+            return Err(XmlSignatureError::DigestMismatch);
+        }
+
+        let cert = X509::from_der(&decode(&parts.certificate)?)?;
+        self.verify_certificate(&cert)?;
+
+        let canonical = c14n::canonicalize(&xml[si_start..si_end], &scope)?;
+        let public_key = cert.public_key()?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+        verifier.update(canonical.as_bytes())?;
+
+        if verifier.verify(&decode(&parts.signature_value)?)? {
+            Ok(unsigned)
+        } else {
+            Err(XmlSignatureError::Invalid)
+        }
+    }
+
+    fn verify_certificate(&self, cert: &X509) -> Result<(), XmlSignatureError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+
+        let chain = Stack::new()?;
+        let mut context = X509StoreContext::new()?;
+        let trusted = context.init(store, cert, &chain, |c| {
+            Ok(c.verify_cert()?.then_some(()).ok_or_else(|| c.error()))
+        })?;
+
+        trusted.map_err(|e| XmlSignatureError::UntrustedCertificate(e.to_string()))
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, XmlSignatureError> {
+    let value = value.split_whitespace().collect::<String>();
+
+    BASE64_STANDARD
+        .decode(value)
+        .map_err(|e| XmlSignatureError::Malformed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_signature, XmlSignatureError, XmlSigner, XmlVerifier};
+    use crate::crypto::{pkcs7::tests::test_identity, SignaturePolicy};
+
+    use openssl::x509::store::X509StoreBuilder;
+
+    #[test]
+    pub fn test_sign_and_verify() {
+        let (cert, key) = test_identity();
+        let xml = "<Response xmlns=\"urn:pos\"><Status>SUCCESS</Status><Count>1</Count></Response>";
+
+        let signed = XmlSigner::new(cert.clone(), key).sign(xml).unwrap();
+        assert!(signed.starts_with("<Response xmlns=\"urn:pos\"><Status>"));
+        assert_eq!(strip_signature(&signed), xml);
+
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(cert).unwrap();
+        let verifier = XmlVerifier::new(Some(store.build()), SignaturePolicy::Reject);
+        assert_eq!(verifier.verify(&signed).unwrap(), xml);
+
+        let tampered = signed.replace("SUCCESS", "FAILURE");
+        assert!(matches!(
+            verifier.verify(&tampered),
+            Err(XmlSignatureError::DigestMismatch)
+        ));
+        assert!(matches!(
+            verifier.verify(xml),
+            Err(XmlSignatureError::Missing)
+        ));
+
+        let (untrusted, key) = test_identity();
+        let signed = XmlSigner::new(untrusted, key).sign(xml).unwrap();
+        assert!(matches!(
+            verifier.verify(&signed),
+            Err(XmlSignatureError::UntrustedCertificate(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_sign_empty_root() {
+        let (cert, key) = test_identity();
+
+        let signed = XmlSigner::new(cert, key).sign("<Response/>").unwrap();
+        assert!(signed.starts_with("<Response><ds:Signature "));
+        assert_eq!(strip_signature(&signed), "<Response></Response>");
+    }
+}
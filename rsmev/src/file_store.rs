@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use uuid::Uuid;
+
+pub const BASE_FILE_DIR: &str = "./ftp_data";
+
+/// Storage of the attachments exchanged with the clients.
+///
+/// Files are addressed by the `File.url` of the request body,
+/// e.g. `/2f1c.../report.pdf`.
+pub trait FileStore: Send + Sync + 'static {
+    /// Resolve an inbound file url to a path passed to the service
+    fn resolve(&self, url: &str) -> io::Result<PathBuf>;
+
+    /// Read content of the stored file
+    fn read(&self, url: &str) -> io::Result<Vec<u8>>;
+
+    /// Move a file produced by the service into the store and return its url
+    fn publish(&self, file: &Path) -> io::Result<String>;
+
+    fn delete(&self, url: &str) -> io::Result<()>;
+
+    fn list(&self) -> io::Result<Vec<String>>;
+}
+
+fn relative_path(url: &str) -> io::Result<PathBuf> {
+    let path = Path::new(url.trim_start_matches('/'));
+
+    if path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(path.to_path_buf())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file url `{url}` points outside of the store"),
+        ))
+    }
+}
+
+fn file_name(file: &Path) -> io::Result<String> {
+    file.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{}` is not a file", file.display()),
+            )
+        })
+}
+
+/// Files are stored in a local directory, every published file gets its own
+/// `<uuid>/` folder
+pub struct LocalFileStore {
+    root: PathBuf,
+}
+
+impl LocalFileStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, url: &str) -> io::Result<PathBuf> {
+        Ok(self.root.join(relative_path(url)?))
+    }
+
+    fn list_dir(&self, dir: &Path, urls: &mut Vec<String>) -> io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                self.list_dir(&path, urls)?;
+            } else if let Ok(relative) = path.strip_prefix(&self.root) {
+                urls.push(format!("/{}", relative.to_string_lossy()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LocalFileStore {
+    fn default() -> Self {
+        Self::new(BASE_FILE_DIR)
+    }
+}
+
+impl FileStore for LocalFileStore {
+    fn resolve(&self, url: &str) -> io::Result<PathBuf> {
+        let path = self.path(url)?;
+        std::fs::metadata(&path)?;
+
+        Ok(path)
+    }
+
+    fn read(&self, url: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.path(url)?)
+    }
+
+    fn publish(&self, file: &Path) -> io::Result<String> {
+        let file_name = file_name(file)?;
+        let path_id = Uuid::new_v4().to_string();
+
+        let mut new_path = self.root.join(&path_id);
+        std::fs::create_dir_all(&new_path)?;
+        new_path.push(&file_name);
+
+        // rename doesn't work across file systems
+        if std::fs::rename(file, &new_path).is_err() {
+            std::fs::copy(file, &new_path)?;
+            std::fs::remove_file(file)?;
+        }
+
+        Ok(format!("/{path_id}/{file_name}"))
+    }
+
+    fn delete(&self, url: &str) -> io::Result<()> {
+        let path = self.path(url)?;
+        std::fs::remove_file(&path)?;
+
+        // remove the `<uuid>/` folder created by `publish`
+        if let Some(parent) = path.parent().filter(|p| *p != self.root) {
+            let _ = std::fs::remove_dir(parent);
+        }
+
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut urls = Vec::new();
+        self.list_dir(&self.root, &mut urls)?;
+
+        Ok(urls)
+    }
+}
+
+/// Files are kept in memory, intended for tests.
+///
+/// The service gets real paths: `resolve` writes the file to a temporary
+/// directory owned by the store, it is removed when the store is dropped.
+/// Like `LocalFileStore::publish`, `publish` takes the file of the service
+/// and removes it from the disk.
+pub struct MemoryFileStore {
+    files: Mutex<HashMap<String, Vec<u8>>>,
+    dir: PathBuf,
+}
+
+impl MemoryFileStore {
+    pub fn new() -> Self {
+        Self {
+            files: Mutex::new(HashMap::new()),
+            dir: std::env::temp_dir().join(format!("rsmev-{}", Uuid::new_v4())),
+        }
+    }
+
+    /// Directory of the resolved files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn insert(&self, url: impl Into<String>, content: impl Into<Vec<u8>>) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(url.into(), content.into());
+    }
+
+    fn not_found(url: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("file `{url}` not found"))
+    }
+}
+
+impl Default for MemoryFileStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MemoryFileStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl FileStore for MemoryFileStore {
+    fn resolve(&self, url: &str) -> io::Result<PathBuf> {
+        let content = self.read(url)?;
+
+        let path = self.dir.join(relative_path(url)?);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, content)?;
+
+        Ok(path)
+    }
+
+    fn read(&self, url: &str) -> io::Result<Vec<u8>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .ok_or_else(|| Self::not_found(url))
+    }
+
+    fn publish(&self, file: &Path) -> io::Result<String> {
+        let content = std::fs::read(file)?;
+        std::fs::remove_file(file)?;
+
+        let url = format!("/{}/{}", Uuid::new_v4(), file_name(file)?);
+        self.insert(url.clone(), content);
+
+        Ok(url)
+    }
+
+    fn delete(&self, url: &str) -> io::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .remove(url)
+            .ok_or_else(|| Self::not_found(url))?;
+
+        // the resolved copy, if any
+        let _ = std::fs::remove_file(self.dir.join(relative_path(url)?));
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        Ok(self.files.lock().unwrap().keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileStore, LocalFileStore, MemoryFileStore};
+
+    #[test]
+    pub fn test_local_publish() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&root).unwrap();
+        let store = LocalFileStore::new(&root);
+
+        let file = root.join("response.txt");
+        std::fs::write(&file, "content").unwrap();
+
+        let url = store.publish(&file).unwrap();
+        assert!(url.ends_with("/response.txt"));
+        assert!(!file.exists());
+        assert_eq!(store.list().unwrap(), vec![url.clone()]);
+        assert_eq!(store.read(&url).unwrap(), b"content");
+
+        store.delete(&url).unwrap();
+        assert!(store.list().unwrap().is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn test_local_outside_of_root() {
+        let store = LocalFileStore::new("./ftp_data");
+
+        let err = store.resolve("/../Cargo.toml").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    pub fn test_memory() {
+        let store = MemoryFileStore::new();
+        store.insert("/request/file.txt", "content");
+
+        let path = store.resolve("/request/file.txt").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"content");
+        assert!(store.resolve("/request/other.txt").is_err());
+        assert!(store.resolve("/../file.txt").is_err());
+
+        let response = store.dir().join("response.txt");
+        std::fs::write(&response, "response").unwrap();
+        let url = store.publish(&response).unwrap();
+        assert_eq!(store.read(&url).unwrap(), b"response");
+
+        store.delete("/request/file.txt").unwrap();
+        assert!(!path.exists());
+        assert_eq!(store.list().unwrap(), vec![url]);
+
+        let dir = store.dir().to_path_buf();
+        drop(store);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,517 @@
+//! Minimal FTP server for attachment exchange, serves the same directory as
+//! [`LocalFileStore`](crate::file_store::LocalFileStore).
+//!
+//! Only passive mode and binary transfers are supported, which is enough for
+//! the SMEV adapter clients.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+};
+
+use crate::accept::AcceptBackoff;
+use crate::file_store::BASE_FILE_DIR;
+
+const DATA_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct FtpConfig {
+    pub root: PathBuf,
+    pub user: String,
+    /// Random by default, so the server is not open with a known password
+    pub password: String,
+    pub passive_ports: RangeInclusive<u16>,
+    /// Address sent in `PASV` replies, local address of the control connection by default
+    pub passive_host: Option<IpAddr>,
+}
+
+impl Default for FtpConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from(BASE_FILE_DIR),
+            user: "admin".to_string(),
+            password: uuid::Uuid::new_v4().simple().to_string(),
+            passive_ports: 40000..=40009,
+            passive_host: None,
+        }
+    }
+}
+
+pub async fn serve(listener: TcpListener, config: FtpConfig) -> io::Result<()> {
+    let config = Arc::new(config);
+    let mut backoff = AcceptBackoff::default();
+
+    loop {
+        // a failed accept, e.g. out of file descriptors, must not stop the server
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => {
+                backoff.reset();
+                connection
+            }
+            Err(e) => {
+                if let Some(delay) = backoff.next(&e) {
+                    tokio::time::sleep(delay).await;
+                }
+                continue;
+            }
+        };
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            tracing::debug!(%addr, "ftp connection");
+            if let Err(e) = Session::new(config).run(stream).await {
+                tracing::warn!(%addr, error = %e, "ftp session failed");
+            }
+        });
+    }
+}
+
+struct Session {
+    config: Arc<FtpConfig>,
+    user: Option<String>,
+    logged_in: bool,
+    /// current directory, relative to the root
+    cwd: PathBuf,
+    passive: Option<TcpListener>,
+    /// Data connections are accepted from the host of the control connection only
+    peer_ip: Option<IpAddr>,
+}
+
+enum Reply {
+    Continue,
+    Quit,
+}
+
+impl Session {
+    fn new(config: Arc<FtpConfig>) -> Self {
+        Self {
+            config,
+            user: None,
+            logged_in: false,
+            cwd: PathBuf::new(),
+            passive: None,
+            peer_ip: None,
+        }
+    }
+
+    async fn run(mut self, stream: TcpStream) -> io::Result<()> {
+        let local_addr = stream.local_addr()?;
+        self.peer_ip = Some(stream.peer_addr()?.ip());
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        write_reply(&mut writer, "220 rsmev ftp server ready").await?;
+
+        while let Some(line) = lines.next_line().await? {
+            let (command, argument) = match line.trim_end().split_once(' ') {
+                Some((command, argument)) => (command.to_ascii_uppercase(), argument.trim()),
+                None => (line.trim_end().to_ascii_uppercase(), ""),
+            };
+
+            match self
+                .command(&mut writer, local_addr, &command, argument)
+                .await?
+            {
+                Reply::Continue => {}
+                Reply::Quit => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn command(
+        &mut self,
+        writer: &mut OwnedWriteHalf,
+        local_addr: SocketAddr,
+        command: &str,
+        argument: &str,
+    ) -> io::Result<Reply> {
+        let public = matches!(command, "USER" | "PASS" | "QUIT" | "SYST" | "FEAT" | "NOOP");
+        if !self.logged_in && !public {
+            write_reply(writer, "530 Not logged in").await?;
+            return Ok(Reply::Continue);
+        }
+
+        match command {
+            "USER" => {
+                self.user = Some(argument.to_string());
+                self.logged_in = false;
+                write_reply(writer, "331 Password required").await?;
+            }
+            "PASS" => {
+                if self.user.as_deref() == Some(&self.config.user)
+                    && argument == self.config.password
+                {
+                    self.logged_in = true;
+                    write_reply(writer, "230 Logged in").await?;
+                } else {
+                    write_reply(writer, "530 Login incorrect").await?;
+                }
+            }
+            "QUIT" => {
+                write_reply(writer, "221 Bye").await?;
+                return Ok(Reply::Quit);
+            }
+            "SYST" => write_reply(writer, "215 UNIX Type: L8").await?,
+            "FEAT" => {
+                write_reply(
+                    writer,
+                    "211-Features:\r\n EPSV\r\n PASV\r\n SIZE\r\n211 End",
+                )
+                .await?
+            }
+            "NOOP" => write_reply(writer, "200 OK").await?,
+            "TYPE" | "MODE" | "STRU" => write_reply(writer, "200 OK").await?,
+            "PWD" | "XPWD" => {
+                let reply = format!("257 \"/{}\"", self.cwd.to_string_lossy());
+                write_reply(writer, &reply).await?;
+            }
+            "CWD" | "CDUP" => {
+                let argument = if command == "CDUP" { ".." } else { argument };
+                let cwd = self.relative(argument);
+
+                if self.config.root.join(&cwd).is_dir() {
+                    self.cwd = cwd;
+                    write_reply(writer, "250 Directory changed").await?;
+                } else {
+                    write_reply(writer, "550 No such directory").await?;
+                }
+            }
+            "PASV" => match self.open_passive(local_addr.ip()).await {
+                Ok(IpAddr::V4(ip)) => {
+                    let port = self.passive_port();
+                    let [h1, h2, h3, h4] = ip.octets();
+                    let reply = format!(
+                        "227 Entering Passive Mode ({h1},{h2},{h3},{h4},{},{})",
+                        port >> 8,
+                        port & 0xff
+                    );
+                    write_reply(writer, &reply).await?;
+                }
+                Ok(IpAddr::V6(_)) => write_reply(writer, "522 Use EPSV").await?,
+                Err(_) => write_reply(writer, "425 No passive port available").await?,
+            },
+            "EPSV" => match self.open_passive(local_addr.ip()).await {
+                Ok(_) => {
+                    let reply = format!(
+                        "229 Entering Extended Passive Mode (|||{}|)",
+                        self.passive_port()
+                    );
+                    write_reply(writer, &reply).await?;
+                }
+                Err(_) => write_reply(writer, "425 No passive port available").await?,
+            },
+            "LIST" | "NLST" => {
+                // clients may pass `ls` flags like `-la`
+                let argument = if argument.starts_with('-') {
+                    ""
+                } else {
+                    argument
+                };
+                let path = self.path(argument);
+
+                match list(&path, command == "NLST").await {
+                    Ok(listing) => self.transfer_out(writer, listing.as_bytes()).await?,
+                    Err(_) => write_reply(writer, "550 No such directory").await?,
+                }
+            }
+            "RETR" => match tokio::fs::read(self.path(argument)).await {
+                Ok(content) => self.transfer_out(writer, &content).await?,
+                Err(_) => write_reply(writer, "550 No such file").await?,
+            },
+            "STOR" => self.transfer_in(writer, argument).await?,
+            "SIZE" => match tokio::fs::metadata(self.path(argument)).await {
+                Ok(m) if m.is_file() => write_reply(writer, &format!("213 {}", m.len())).await?,
+                _ => write_reply(writer, "550 No such file").await?,
+            },
+            "DELE" => match tokio::fs::remove_file(self.path(argument)).await {
+                Ok(_) => write_reply(writer, "250 File deleted").await?,
+                Err(_) => write_reply(writer, "550 No such file").await?,
+            },
+            "MKD" | "XMKD" => match tokio::fs::create_dir_all(self.path(argument)).await {
+                Ok(_) => {
+                    let reply = format!("257 \"/{}\" created", self.relative(argument).display());
+                    write_reply(writer, &reply).await?;
+                }
+                Err(_) => write_reply(writer, "550 Can't create directory").await?,
+            },
+            "RMD" | "XRMD" => match tokio::fs::remove_dir(self.path(argument)).await {
+                Ok(_) => write_reply(writer, "250 Directory removed").await?,
+                Err(_) => write_reply(writer, "550 Can't remove directory").await?,
+            },
+            _ => write_reply(writer, "502 Command not implemented").await?,
+        }
+
+        Ok(Reply::Continue)
+    }
+
+    /// Resolve `path` against the current directory without leaving the root
+    fn relative(&self, path: &str) -> PathBuf {
+        let mut relative = if path.starts_with('/') {
+            PathBuf::new()
+        } else {
+            self.cwd.clone()
+        };
+
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(c) => relative.push(c),
+                Component::ParentDir => {
+                    relative.pop();
+                }
+                _ => {}
+            }
+        }
+
+        relative
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.config.root.join(self.relative(path))
+    }
+
+    async fn open_passive(&mut self, local_ip: IpAddr) -> io::Result<IpAddr> {
+        let ip = self.config.passive_host.unwrap_or(local_ip);
+
+        for port in self.config.passive_ports.clone() {
+            if let Ok(listener) = TcpListener::bind(SocketAddr::new(local_ip, port)).await {
+                self.passive = Some(listener);
+                return Ok(ip);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "all passive ports are in use",
+        ))
+    }
+
+    fn passive_port(&self) -> u16 {
+        self.passive
+            .as_ref()
+            .and_then(|l| l.local_addr().ok())
+            .map(|a| a.port())
+            .unwrap_or_default()
+    }
+
+    async fn data_connection(&mut self) -> io::Result<TcpStream> {
+        let listener = self
+            .passive
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "use PASV first"))?;
+
+        let peer_ip = self.peer_ip.map(|ip| ip.to_canonical());
+        let accept = async {
+            loop {
+                let (stream, addr) = listener.accept().await?;
+                // another host must not steal the transfer by guessing the port
+                if Some(addr.ip().to_canonical()) == peer_ip {
+                    return Ok::<_, io::Error>(stream);
+                }
+
+                tracing::warn!(%addr, "ftp data connection from another host rejected");
+            }
+        };
+
+        tokio::time::timeout(DATA_CONNECTION_TIMEOUT, accept)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "data connection timeout"))?
+    }
+
+    async fn transfer_out(
+        &mut self,
+        writer: &mut OwnedWriteHalf,
+        content: &[u8],
+    ) -> io::Result<()> {
+        write_reply(writer, "150 Opening data connection").await?;
+
+        let sent = async {
+            let mut data = self.data_connection().await?;
+            data.write_all(content).await?;
+            data.shutdown().await
+        }
+        .await;
+
+        match sent {
+            Ok(_) => write_reply(writer, "226 Transfer complete").await,
+            Err(_) => write_reply(writer, "425 Can't open data connection").await,
+        }
+    }
+
+    async fn transfer_in(&mut self, writer: &mut OwnedWriteHalf, file: &str) -> io::Result<()> {
+        let path = self.path(file);
+        write_reply(writer, "150 Opening data connection").await?;
+
+        let received = async {
+            let mut data = self.data_connection().await?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            let mut file = tokio::fs::File::create(&path).await?;
+            tokio::io::copy(&mut data, &mut file).await?;
+            file.flush().await
+        }
+        .await;
+
+        match received {
+            Ok(_) => write_reply(writer, "226 Transfer complete").await,
+            Err(e) => {
+                tracing::warn!(file = %path.display(), error = %e, "ftp upload failed");
+                write_reply(writer, "451 Transfer aborted").await
+            }
+        }
+    }
+}
+
+async fn list(path: &Path, names_only: bool) -> io::Result<String> {
+    let mut entries = tokio::fs::read_dir(path).await?;
+    let mut listing = String::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if names_only {
+            listing.push_str(&name);
+        } else {
+            let metadata = entry.metadata().await?;
+            let kind = if metadata.is_dir() { 'd' } else { '-' };
+            listing.push_str(&format!(
+                "{kind}rw-r--r-- 1 ftp ftp {:>12} Jan 01 00:00 {name}",
+                metadata.len()
+            ));
+        }
+        listing.push_str("\r\n");
+    }
+
+    Ok(listing)
+}
+
+async fn write_reply(writer: &mut OwnedWriteHalf, reply: &str) -> io::Result<()> {
+    writer.write_all(reply.as_bytes()).await?;
+    writer.write_all(b"\r\n").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{serve, FtpConfig};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpListener, TcpSocket, TcpStream,
+        },
+    };
+
+    struct Control {
+        reader: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl Control {
+        async fn reply(&mut self) -> String {
+            self.reader.next_line().await.unwrap().unwrap()
+        }
+
+        async fn send(&mut self, command: &str) -> String {
+            self.writer
+                .write_all(format!("{command}\r\n").as_bytes())
+                .await
+                .unwrap();
+            self.reply().await
+        }
+
+        async fn passive_port(&mut self) -> u16 {
+            let reply = self.send("EPSV").await;
+            reply.split('|').nth(3).unwrap().parse().unwrap()
+        }
+
+        async fn passive(&mut self) -> TcpStream {
+            let port = self.passive_port().await;
+            TcpStream::connect(("127.0.0.1", port)).await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_retrieve() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&root).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = FtpConfig {
+            root: root.clone(),
+            password: "secret".to_string(),
+            passive_ports: 0..=0,
+            ..Default::default()
+        };
+        tokio::spawn(serve(listener, config));
+
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut control = Control {
+            reader: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        assert!(control.reply().await.starts_with("220"));
+        assert!(control.send("RETR file.txt").await.starts_with("530"));
+        assert!(control.send("USER admin").await.starts_with("331"));
+        assert!(control.send("PASS 12345678").await.starts_with("530"));
+        assert!(control.send("USER admin").await.starts_with("331"));
+        assert!(control.send("PASS secret").await.starts_with("230"));
+
+        let mut data = control.passive().await;
+        assert!(control
+            .send("STOR request/file.txt")
+            .await
+            .starts_with("150"));
+        data.write_all(b"content").await.unwrap();
+        drop(data);
+        assert!(control.reply().await.starts_with("226"));
+
+        assert_eq!(
+            std::fs::read(root.join("request/file.txt")).unwrap(),
+            b"content"
+        );
+
+        let mut data = control.passive().await;
+        assert!(control
+            .send("RETR /request/file.txt")
+            .await
+            .starts_with("150"));
+        let mut content = Vec::new();
+        data.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"content");
+        assert!(control.reply().await.starts_with("226"));
+
+        // a connection from another address is dropped, the client still gets the file
+        let port = control.passive_port().await;
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+        let mut thief = socket.connect(([127, 0, 0, 1], port).into()).await.unwrap();
+        let mut data = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        assert!(control
+            .send("RETR /request/file.txt")
+            .await
+            .starts_with("150"));
+        let mut stolen = Vec::new();
+        let _ = thief.read_to_end(&mut stolen).await;
+        assert!(stolen.is_empty());
+        let mut content = Vec::new();
+        data.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"content");
+        assert!(control.reply().await.starts_with("226"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
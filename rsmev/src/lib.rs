@@ -1,5 +1,24 @@
+#[cfg(any(feature = "ftp", feature = "tls"))]
+mod accept;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod confirm_queue;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod file_store;
+#[cfg(feature = "ftp")]
+pub mod ftp;
 mod server;
 pub mod service;
+#[cfg(feature = "xsd")]
+pub mod xsd;
 
-pub use server::serve;
+pub use server::{api, body};
+pub use server::{
+    read_api_keys, read_entrypoints, serve, serve_with_config, serve_with_file_store,
+    serve_with_shutdown, ApiKey, Entrypoint, RsmevServer, RsmevServerBuilder, ServeConfig,
+    DEFAULT_CHANNEL_BUFFER_SIZE, DEFAULT_CONCURRENCY, DEFAULT_NODE_ID, DEFAULT_NODE_ID_HEADER,
+    DEFAULT_PATH_PREFIX, DEFAULT_QUEUE_TTL,
+};
+#[cfg(feature = "tls")]
+pub use server::{ClientCertificate, TlsConfig};
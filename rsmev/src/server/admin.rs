@@ -0,0 +1,435 @@
+//! Inspection of the entrypoints, their nodes and queues
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{
+    body::Body,
+    client::{Client, Queue, QueuedResponse},
+    error::ApiError,
+    serve::Rsmev,
+};
+use crate::confirm_queue::QueueItem;
+use crate::service::Service;
+
+type AdminState<S> = State<Arc<Rsmev<S>>>;
+
+pub(crate) fn routes<S: Service>(state: Arc<Rsmev<S>>) -> Router {
+    let message = "/entrypoints/:entrypoint_id/nodes/:node_id/messages/:request_id";
+    let dead_letter = "/entrypoints/:entrypoint_id/nodes/:node_id/dead-letters/:request_id";
+
+    Router::new()
+        .route("/entrypoints", get(list_entrypoints))
+        .route("/entrypoints/:entrypoint_id", get(get_entrypoint))
+        .route(
+            "/entrypoints/:entrypoint_id/nodes/:node_id/messages",
+            get(list_messages),
+        )
+        .route(message, get(peek_message).delete(delete_message))
+        .route(&format!("{message}/redeliver"), post(redeliver_message))
+        .route(
+            "/entrypoints/:entrypoint_id/nodes/:node_id/dead-letters",
+            get(list_dead_letters),
+        )
+        .route(
+            dead_letter,
+            get(peek_dead_letter).delete(delete_dead_letter),
+        )
+        .route(&format!("{dead_letter}/requeue"), post(requeue_dead_letter))
+        .with_state(state)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EntrypointInfo {
+    id: Uuid,
+    /// `None` for an auto-created entrypoint
+    name: Option<String>,
+    /// Requests waiting for the service
+    queued: usize,
+    nodes: Vec<NodeInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeInfo {
+    node_id: String,
+    len: usize,
+    in_flight: usize,
+    oldest_age_ms: Option<u128>,
+    dead_letters: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageInfo {
+    request_id: Uuid,
+    message_id: Uuid,
+    in_flight: bool,
+    /// Times the response was taken without a confirmation
+    deliveries: u32,
+    age_ms: u128,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    body: Option<Body>,
+}
+
+impl MessageInfo {
+    fn new(request_id: Uuid, item: &QueueItem<QueuedResponse>, with_body: bool) -> Self {
+        Self {
+            request_id,
+            message_id: item.value().message_id,
+            in_flight: item.is_taken(),
+            deliveries: item.deliveries(),
+            age_ms: item.age().as_millis(),
+            body: with_body.then(|| item.value().body.clone()),
+        }
+    }
+}
+
+async fn list_entrypoints<S: Service>(State(state): AdminState<S>) -> Json<Vec<EntrypointInfo>> {
+    let mut ids: Vec<Uuid> = state.registry.ids().collect();
+    for client in state.clients.iter() {
+        if !ids.contains(client.key()) {
+            ids.push(*client.key());
+        }
+    }
+
+    Json(
+        ids.into_iter()
+            .map(|id| entrypoint_info(&state, id))
+            .collect(),
+    )
+}
+
+async fn get_entrypoint<S: Service>(
+    State(state): AdminState<S>,
+    Path(entrypoint_id): Path<Uuid>,
+) -> Result<Json<EntrypointInfo>, ApiError> {
+    if !state.registry.contains(entrypoint_id) && !state.clients.contains_key(&entrypoint_id) {
+        return Err(ApiError::UnknownEntrypoint(entrypoint_id));
+    }
+
+    Ok(Json(entrypoint_info(&state, entrypoint_id)))
+}
+
+async fn list_messages<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id)): Path<(Uuid, String)>,
+) -> Result<Json<Vec<MessageInfo>>, ApiError> {
+    let mut messages = inspect(&state, entrypoint_id, &node_id, |queue| {
+        queue
+            .iter()
+            .map(|(id, item)| MessageInfo::new(*id, item, false))
+            .collect::<Vec<_>>()
+    })?;
+    messages.sort_by_key(|m| std::cmp::Reverse(m.age_ms));
+
+    Ok(Json(messages))
+}
+
+async fn peek_message<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id, request_id)): Path<(Uuid, String, Uuid)>,
+) -> Result<Json<MessageInfo>, ApiError> {
+    inspect(&state, entrypoint_id, &node_id, |queue| {
+        queue
+            .get(&request_id)
+            .map(|item| MessageInfo::new(request_id, item, true))
+    })?
+    .map(Json)
+    .ok_or(ApiError::UnknownRequest(request_id))
+}
+
+async fn delete_message<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id, request_id)): Path<(Uuid, String, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let client = client(&state, entrypoint_id)?;
+    if !client.remove(&node_id, &request_id) {
+        return Err(ApiError::UnknownRequest(request_id));
+    }
+
+    tracing::info!(%entrypoint_id, node_id, %request_id, "message removed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn redeliver_message<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id, request_id)): Path<(Uuid, String, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let client = client(&state, entrypoint_id)?;
+    if !client.redeliver(&node_id, &request_id) {
+        return Err(ApiError::UnknownRequest(request_id));
+    }
+
+    tracing::info!(%entrypoint_id, node_id, %request_id, "message redelivered");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_dead_letters<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id)): Path<(Uuid, String)>,
+) -> Result<Json<Vec<MessageInfo>>, ApiError> {
+    let mut messages = inspect(&state, entrypoint_id, &node_id, |queue| {
+        queue
+            .dead_letters()
+            .map(|(id, item)| MessageInfo::new(*id, item, false))
+            .collect::<Vec<_>>()
+    })?;
+    messages.sort_by_key(|m| std::cmp::Reverse(m.age_ms));
+
+    Ok(Json(messages))
+}
+
+async fn peek_dead_letter<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id, request_id)): Path<(Uuid, String, Uuid)>,
+) -> Result<Json<MessageInfo>, ApiError> {
+    inspect(&state, entrypoint_id, &node_id, |queue| {
+        queue
+            .get_dead_letter(&request_id)
+            .map(|item| MessageInfo::new(request_id, item, true))
+    })?
+    .map(Json)
+    .ok_or(ApiError::UnknownRequest(request_id))
+}
+
+async fn delete_dead_letter<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id, request_id)): Path<(Uuid, String, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let client = client(&state, entrypoint_id)?;
+    if !client.remove_dead_letter(&node_id, &request_id) {
+        return Err(ApiError::UnknownRequest(request_id));
+    }
+
+    tracing::info!(%entrypoint_id, node_id, %request_id, "dead letter removed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Put the dead letter back to the queue, its deliveries are counted anew
+async fn requeue_dead_letter<S: Service>(
+    State(state): AdminState<S>,
+    Path((entrypoint_id, node_id, request_id)): Path<(Uuid, String, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let client = client(&state, entrypoint_id)?;
+    if !client.requeue_dead_letter(&node_id, &request_id) {
+        return Err(ApiError::UnknownRequest(request_id));
+    }
+
+    tracing::info!(%entrypoint_id, node_id, %request_id, "dead letter requeued");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn entrypoint_info<S: Service>(state: &Rsmev<S>, id: Uuid) -> EntrypointInfo {
+    let name = state.registry.name(id).map(str::to_string);
+    let Some(client) = state.clients.get(&id) else {
+        // registered, but not opened yet
+        return EntrypointInfo {
+            id,
+            name,
+            queued: 0,
+            nodes: Vec::new(),
+        };
+    };
+
+    let mut nodes: Vec<NodeInfo> = client
+        .node_stats()
+        .into_iter()
+        .map(|(node_id, stats)| NodeInfo {
+            node_id,
+            len: stats.len,
+            in_flight: stats.in_flight,
+            oldest_age_ms: stats.oldest_age.as_ref().map(Duration::as_millis),
+            dead_letters: stats.dead_letters,
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+
+    EntrypointInfo {
+        id,
+        name,
+        queued: client.queued(),
+        nodes,
+    }
+}
+
+fn client<S: Service>(
+    state: &Rsmev<S>,
+    entrypoint_id: Uuid,
+) -> Result<dashmap::mapref::one::Ref<'_, Uuid, Client<S>>, ApiError> {
+    state
+        .clients
+        .get(&entrypoint_id)
+        .ok_or(ApiError::UnknownEntrypoint(entrypoint_id))
+}
+
+fn inspect<S: Service, R>(
+    state: &Rsmev<S>,
+    entrypoint_id: Uuid,
+    node_id: &str,
+    f: impl FnOnce(&mut Queue<QueuedResponse>) -> R,
+) -> Result<R, ApiError> {
+    client(state, entrypoint_id)?
+        .inspect(node_id, f)
+        .ok_or_else(|| ApiError::UnknownNode(node_id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::RequestStatus;
+    use crate::server::serve::tests::{call, pop, send_ok, wait_status, EchoService};
+    use crate::RsmevServer;
+
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+    use uuid::Uuid;
+
+    const TTL: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    pub async fn test_messages() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .admin_path_prefix("/admin")
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+        let entrypoint = format!("/admin/entrypoints/{entrypoint_id}");
+        let messages = format!("{entrypoint}/nodes/master/messages");
+
+        let kept = send_ok(&router, entrypoint_id, "kept").await;
+        let removed = send_ok(&router, entrypoint_id, "removed").await;
+        for request_id in [kept, removed] {
+            wait_status(
+                &router,
+                entrypoint_id,
+                request_id,
+                RequestStatus::ResponseReady,
+            )
+            .await;
+        }
+
+        let (status, list) = call(&router, "GET", "/admin/entrypoints", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list[0]["id"], entrypoint_id.to_string());
+        assert_eq!(list[0]["nodes"][0]["len"], 2);
+
+        let (status, list) = call(&router, "GET", &messages, &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list.as_array().unwrap().len(), 2);
+
+        let uri = format!("{messages}/{kept}");
+        let (status, message) = call(&router, "GET", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(message["inFlight"], false);
+        assert!(message["xml"].is_string(), "{message}");
+
+        let uri = format!("{messages}/{removed}");
+        let (status, _) = call(&router, "DELETE", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        for method in ["GET", "DELETE"] {
+            let (status, body) = call(&router, method, &uri, &[], None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+            assert_eq!(body["code"], "UNKNOWN_REQUEST");
+        }
+
+        // a taken response is made the next one to take
+        let (_, response) = pop(&router, entrypoint_id).await;
+        assert_eq!(response["requestId"], kept.to_string());
+        let uri = format!("{messages}/{kept}");
+        assert_eq!(
+            call(&router, "GET", &uri, &[], None).await.1["inFlight"],
+            true
+        );
+
+        let (status, _) = call(&router, "POST", &format!("{uri}/redeliver"), &[], None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            call(&router, "GET", &uri, &[], None).await.1["inFlight"],
+            false
+        );
+        let (_, response) = pop(&router, entrypoint_id).await;
+        assert_eq!(response["deliveryAttempt"], 2);
+
+        let uri = format!("{messages}/{removed}/redeliver");
+        let (status, _) = call(&router, "POST", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let uri = format!("/admin/entrypoints/{}", Uuid::new_v4());
+        let (status, body) = call(&router, "GET", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+        assert_eq!(body["code"], "UNKNOWN_ENTRYPOINT");
+    }
+
+    #[tokio::test]
+    pub async fn test_dead_letters() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .admin_path_prefix("/admin")
+            .queue_ttl(TTL)
+            .max_deliveries(1)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+        let dead_letters = format!("/admin/entrypoints/{entrypoint_id}/nodes/master/dead-letters");
+
+        // taken once and not confirmed, the next pop moves it to the dead letters
+        let request_id = send_ok(&router, entrypoint_id, "poison").await;
+        wait_status(
+            &router,
+            entrypoint_id,
+            request_id,
+            RequestStatus::ResponseReady,
+        )
+        .await;
+        let dead_letter = || async {
+            let _ = pop(&router, entrypoint_id).await;
+            tokio::time::sleep(TTL + Duration::from_millis(10)).await;
+            assert_eq!(pop(&router, entrypoint_id).await.0, StatusCode::NOT_FOUND);
+        };
+        dead_letter().await;
+
+        let (status, list) = call(&router, "GET", &dead_letters, &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list[0]["requestId"], request_id.to_string());
+
+        let uri = format!("{dead_letters}/{request_id}");
+        let (status, message) = call(&router, "GET", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(message["xml"].is_string(), "{message}");
+
+        // requeued, it is delivered again with the deliveries counted anew
+        let (status, _) = call(&router, "POST", &format!("{uri}/requeue"), &[], None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, list) = call(&router, "GET", &dead_letters, &[], None).await;
+        assert_eq!(list.as_array().unwrap().len(), 0);
+        wait_status(
+            &router,
+            entrypoint_id,
+            request_id,
+            RequestStatus::ResponseReady,
+        )
+        .await;
+
+        dead_letter().await;
+        let (status, _) = call(&router, "DELETE", &uri, &[], None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        for (method, uri) in [("GET", uri.clone()), ("POST", format!("{uri}/requeue"))] {
+            let (status, body) = call(&router, method, &uri, &[], None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+            assert_eq!(body["code"], "UNKNOWN_REQUEST");
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Json messages of the adapter REST API, shared by the server and the client
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::body::{self, Body};
+
+/// `POST /sendrequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendRequest {
+    #[serde(flatten)]
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendResponse {
+    pub request_id: Uuid,
+}
+
+/// `POST /getresponse`, the body is `null` when there is no response
+///
+/// `message_id` and `rec_id` are the same for every delivery of a response.
+/// The delivery details are set by the mock only, timestamps are
+/// milliseconds since the unix epoch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetResponse {
+    pub rec_id: Uuid,
+    pub request_id: Uuid,
+    pub message_id: Uuid,
+    /// 1 for the first delivery, counted again from 1 after a restart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_attempt: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queued_at_ms: Option<u64>,
+    /// First delivery since the start of the adapter, it is not journaled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_delivered_at_ms: Option<u64>,
+    #[serde(flatten)]
+    pub body: Body,
+}
+
+impl GetResponse {
+    /// Deserialize the response xml
+    pub fn content<T: DeserializeOwned>(&self) -> Result<T, body::Error> {
+        self.body.xml.deserialize()
+    }
+}
+
+/// `POST /confirmprocessing/:request_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmResponse {
+    pub request_id: Uuid,
+}
+
+/// Body of every rejected request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    /// Every schema validation error
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+/// Stage of a request, from `sendrequest` to the confirmation of its response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RequestStatus {
+    /// Waiting for a free worker
+    Queued,
+    Processing,
+    /// Response waits for the node
+    ResponseReady,
+    /// Service failed, the fault waits for the node
+    Failed,
+    /// Taken by the node and not confirmed yet
+    Delivered,
+    Confirmed,
+    /// Moved to the dead-letter queue after too many deliveries
+    DeadLetter,
+}
+
+/// `GET /status/:request_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusResponse {
+    pub request_id: Uuid,
+    pub node_id: String,
+    pub status: RequestStatus,
+    /// Response is a fault, it stays set after the delivery
+    pub fault: bool,
+    /// Milliseconds since the unix epoch
+    pub updated_at_ms: u64,
+}
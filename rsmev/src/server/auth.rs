@@ -0,0 +1,222 @@
+use std::{collections::HashMap, io, path::Path, sync::Arc};
+
+use axum::{
+    extract::{Path as UrlParams, Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::error::ApiError;
+
+const API_KEY_HEADER_NAME: &str = "x-api-key";
+
+/// Static credential of the API, sent as `Authorization: Bearer <key>`
+/// or `X-Api-Key: <key>`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    pub key: String,
+    /// Entrypoints the key is allowed to use, any entrypoint when empty
+    #[serde(default)]
+    pub entrypoints: Vec<Uuid>,
+}
+
+impl ApiKey {
+    pub fn new(key: impl Into<String>, entrypoints: Vec<Uuid>) -> Self {
+        Self {
+            key: key.into(),
+            entrypoints,
+        }
+    }
+}
+
+/// Read a json array of the api keys
+pub fn read_api_keys(path: impl AsRef<Path>) -> io::Result<Vec<ApiKey>> {
+    let content = std::fs::read(path)?;
+
+    Ok(serde_json::from_slice(&content)?)
+}
+
+pub(crate) struct Auth {
+    keys: Vec<ApiKey>,
+}
+
+impl Auth {
+    pub fn new(keys: Vec<ApiKey>) -> Self {
+        Self { keys }
+    }
+
+    pub fn authorize(
+        &self,
+        headers: &HeaderMap,
+        entrypoint_id: Option<Uuid>,
+    ) -> Result<(), ApiError> {
+        let credential = credential(headers).ok_or(ApiError::Unauthorized)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|k| constant_time_eq(k.key.as_bytes(), credential.as_bytes()))
+            .ok_or(ApiError::Unauthorized)?;
+
+        match entrypoint_id {
+            Some(id) if !key.entrypoints.is_empty() && !key.entrypoints.contains(&id) => {
+                Err(ApiError::Forbidden(id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Only the keys not restricted to some entrypoints are admins
+    pub fn authorize_admin(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        self.authorize(headers, None)?;
+
+        let credential = credential(headers).unwrap_or_default();
+        let admin = self.keys.iter().any(|k| {
+            k.entrypoints.is_empty() && constant_time_eq(k.key.as_bytes(), credential.as_bytes())
+        });
+
+        admin.then_some(()).ok_or(ApiError::AdminForbidden)
+    }
+}
+
+/// Middleware of the entrypoint routes
+pub(crate) async fn authenticate(
+    State(auth): State<Arc<Auth>>,
+    UrlParams(params): UrlParams<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    // a malformed id is rejected by the handler itself
+    let entrypoint_id = params.get("entrypoint_id").and_then(|id| id.parse().ok());
+    auth.authorize(request.headers(), entrypoint_id)?;
+
+    Ok(next.run(request).await)
+}
+
+/// Middleware of the admin routes
+pub(crate) async fn authenticate_admin(
+    State(auth): State<Arc<Auth>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    auth.authorize_admin(request.headers())?;
+
+    Ok(next.run(request).await)
+}
+
+fn credential(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    bearer
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER_NAME)
+                .and_then(|v| v.to_str().ok())
+        })
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiKey, Auth};
+    use crate::server::body::EncodedXml;
+    use crate::server::error::ApiError;
+    use crate::server::serve::tests::{call, EchoService};
+    use crate::RsmevServer;
+
+    use axum::http::{HeaderMap, HeaderValue, StatusCode};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn test_authorize() {
+        let allowed = Uuid::new_v4();
+        let auth = Auth::new(vec![
+            ApiKey::new("team-a", vec![allowed]),
+            ApiKey::new("admin", Vec::new()),
+        ]);
+
+        let mut bearer = HeaderMap::new();
+        bearer.insert("authorization", HeaderValue::from_static("Bearer team-a"));
+        assert!(auth.authorize(&bearer, Some(allowed)).is_ok());
+        assert!(matches!(
+            auth.authorize(&bearer, Some(Uuid::new_v4())),
+            Err(ApiError::Forbidden(_))
+        ));
+
+        let mut api_key = HeaderMap::new();
+        api_key.insert("x-api-key", HeaderValue::from_static("admin"));
+        assert!(auth.authorize(&api_key, Some(Uuid::new_v4())).is_ok());
+
+        api_key.insert("x-api-key", HeaderValue::from_static("admin2"));
+        assert!(matches!(
+            auth.authorize(&api_key, Some(allowed)),
+            Err(ApiError::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.authorize(&HeaderMap::new(), Some(allowed)),
+            Err(ApiError::Unauthorized)
+        ));
+
+        api_key.insert("x-api-key", HeaderValue::from_static("admin"));
+        assert!(auth.authorize_admin(&api_key).is_ok());
+        assert!(matches!(
+            auth.authorize_admin(&bearer),
+            Err(ApiError::AdminForbidden)
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn test_authenticate() {
+        let allowed = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .admin_path_prefix("/admin")
+            .api_key(ApiKey::new("team-a", vec![allowed]))
+            .api_key(ApiKey::new("admin", Vec::new()))
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        let xml = EncodedXml::encode("<Echo><Text>hi</Text></Echo>");
+        let send = |entrypoint_id: Uuid, headers: &'static [(&'static str, &'static str)]| {
+            let uri = format!("/api/smev/{entrypoint_id}/sendrequest");
+            let router = router.clone();
+            let body = json!({ "xml": xml });
+            async move { call(&router, "POST", &uri, headers, Some(body)).await }
+        };
+        let team_a = &[("authorization", "Bearer team-a")];
+
+        for headers in [&[][..], &[("x-api-key", "team-b")]] {
+            let (status, body) = send(allowed, headers).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{body}");
+            assert_eq!(body["code"], "UNAUTHORIZED");
+        }
+
+        let (status, body) = send(Uuid::new_v4(), team_a).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert_eq!(body["code"], "FORBIDDEN");
+        assert_eq!(send(allowed, team_a).await.0, StatusCode::OK);
+
+        // only a key that is not restricted to some entrypoints is an admin
+        let (status, body) = call(&router, "GET", "/admin/entrypoints", team_a, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert!(body["message"].as_str().unwrap().contains("admin"));
+        let admin = &[("x-api-key", "admin")];
+        let (status, _) = call(&router, "GET", "/admin/entrypoints", admin, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = call(&router, "GET", "/health", &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
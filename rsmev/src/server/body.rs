@@ -1,5 +1,5 @@
 use base64::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct File {
@@ -23,33 +23,94 @@ pub struct EncodedXml {
 }
 
 #[derive(Debug)]
-pub struct Error;
+pub enum Error {
+    Base64(base64::DecodeError),
+    Utf8(std::str::Utf8Error),
+    Deserialize(quick_xml::DeError),
+    Serialize(quick_xml::DeError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Base64(e) => write!(f, "xml is not valid base64: {e}"),
+            Error::Utf8(e) => write!(f, "xml is not valid utf-8: {e}"),
+            Error::Deserialize(e) => write!(f, "xml does not match the request type: {e}"),
+            Error::Serialize(e) => write!(f, "failed to serialize xml: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
 
 impl EncodedXml {
     pub const fn new(content: String) -> Self {
         Self { content }
     }
 
-    pub fn deserialize<'de, T: Deserialize<'de>>(&self) -> Result<T, Error> {
-        let decoded = BASE64_STANDARD.decode(&self.content).map_err(|_| Error)?;
-        println!("XML: {}", unsafe {
-            std::str::from_utf8_unchecked(&decoded)
-        });
-        let cursor = std::io::Cursor::new(decoded);
+    pub fn encode(xml: &str) -> Self {
+        Self::new(BASE64_STANDARD.encode(xml))
+    }
+
+    pub fn decode(&self) -> Result<String, Error> {
+        let decoded = BASE64_STANDARD
+            .decode(&self.content)
+            .map_err(Error::Base64)?;
+
+        String::from_utf8(decoded).map_err(|e| Error::Utf8(e.utf8_error()))
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        Self::deserialize_str(&self.decode()?)
+    }
 
-        let mut deserializer = quick_xml::de::Deserializer::from_reader(cursor);
+    pub fn deserialize_str<T: DeserializeOwned>(xml: &str) -> Result<T, Error> {
+        tracing::debug!(xml);
 
-        T::deserialize(&mut deserializer).map_err(|e| {
-            tracing::error!(err = ?e);
-            Error
-        })
+        quick_xml::de::from_str(xml).map_err(Error::Deserialize)
     }
 
     pub fn serialize<T: Serialize>(content: &T) -> Result<Self, Error> {
-        let serialized = quick_xml::se::to_string(content).map_err(|_| Error)?;
+        Ok(Self::encode(&Self::serialize_to_string(content)?))
+    }
+
+    pub fn serialize_to_string<T: Serialize>(content: &T) -> Result<String, Error> {
+        let serialized = quick_xml::se::to_string(content).map_err(Error::Serialize)?;
+        tracing::debug!(serialized);
+
+        Ok(serialized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EncodedXml, Error};
+    use base64::prelude::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Request {
+        #[serde(rename = "ClientId")]
+        _client_id: String,
+    }
+
+    #[test]
+    pub fn test_deserialize_errors() {
+        let not_base64 = EncodedXml::new("<Request/>".to_string());
+        assert!(matches!(
+            not_base64.deserialize::<Request>(),
+            Err(Error::Base64(_))
+        ));
 
-        println!("Serialized:\n{serialized}");
+        let not_utf8 = EncodedXml::new(BASE64_STANDARD.encode([0xff, 0xfe]));
+        assert!(matches!(
+            not_utf8.deserialize::<Request>(),
+            Err(Error::Utf8(_))
+        ));
 
-        Ok(Self::new(BASE64_STANDARD.encode(&serialized)))
+        let wrong_xml = EncodedXml::new(BASE64_STANDARD.encode("<Request><Id>1</Id></Request>"));
+        assert!(matches!(
+            wrong_xml.deserialize::<Request>(),
+            Err(Error::Deserialize(_))
+        ));
     }
 }
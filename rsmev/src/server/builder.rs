@@ -0,0 +1,163 @@
+use std::{io, path::PathBuf, sync::Arc, time::Duration};
+
+use super::auth::ApiKey;
+use super::config::ServeConfig;
+use super::entrypoint::Entrypoint;
+use super::serve::RsmevServer;
+#[cfg(feature = "tls")]
+use super::tls::TlsConfig;
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoConfig;
+use crate::file_store::{FileStore, LocalFileStore};
+use crate::service::Service;
+
+/// Builder of [`RsmevServer`], every option defaults to [`ServeConfig::default`]
+pub struct RsmevServerBuilder<S> {
+    config: ServeConfig,
+    services: Vec<(String, S)>,
+}
+
+impl<S: Service> RsmevServerBuilder<S> {
+    pub fn new() -> Self {
+        Self {
+            config: ServeConfig::default(),
+            services: Vec::new(),
+        }
+    }
+
+    /// Register a service for the entrypoints bound to `name`, see
+    /// `Entrypoint::service`
+    pub fn service(mut self, name: impl Into<String>, service: S) -> Self {
+        self.services.push((name.into(), service));
+        self
+    }
+
+    /// Replace all the options at once
+    pub fn config(mut self, config: ServeConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn file_store(mut self, files: impl FileStore) -> Self {
+        self.config.file_store = Arc::new(files);
+        self
+    }
+
+    /// Keep the files in a local directory instead of `./ftp_data`
+    pub fn file_dir(self, root: impl Into<PathBuf>) -> Self {
+        self.file_store(LocalFileStore::new(root))
+    }
+
+    /// Journal the responses, see `ServeConfig::journal` for what is lost on a crash
+    pub fn journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.journal = Some(path.into());
+        self
+    }
+
+    pub fn queue_ttl(mut self, ttl: Duration) -> Self {
+        self.config.queue_ttl = ttl;
+        self
+    }
+
+    /// Move a response to the dead-letter queue after `max` deliveries,
+    /// the count restarts with the process, see `ServeConfig::max_deliveries`
+    pub fn max_deliveries(mut self, max: u32) -> Self {
+        self.config.max_deliveries = Some(max);
+        self
+    }
+
+    pub fn channel_buffer_size(mut self, size: usize) -> Self {
+        self.config.channel_buffer_size = size;
+        self
+    }
+
+    /// Wait for room in a full buffer instead of rejecting the request at once
+    pub fn send_timeout(mut self, timeout: Duration) -> Self {
+        self.config.send_timeout = Some(timeout);
+        self
+    }
+
+    /// Requests of an entrypoint handled at once
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.concurrency = concurrency;
+        self
+    }
+
+    /// Requests of a node handled at once
+    pub fn node_concurrency(mut self, concurrency: usize) -> Self {
+        self.config.node_concurrency = Some(concurrency);
+        self
+    }
+
+    pub fn preserve_node_order(mut self, preserve: bool) -> Self {
+        self.config.preserve_node_order = preserve;
+        self
+    }
+
+    pub fn default_node_id(mut self, node_id: impl Into<String>) -> Self {
+        self.config.default_node_id = node_id.into();
+        self
+    }
+
+    pub fn node_id_header(mut self, name: impl Into<String>) -> Self {
+        self.config.node_id_header = name.into();
+        self
+    }
+
+    /// Prefix of the routes, `/api/smev` by default
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.path_prefix = prefix.into();
+        self
+    }
+
+    /// Serve the admin routes under the prefix, e.g. `/admin`
+    pub fn admin_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.admin_path_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn entrypoint(mut self, entrypoint: Entrypoint) -> Self {
+        self.config.entrypoints.push(entrypoint);
+        self
+    }
+
+    pub fn entrypoints(mut self, entrypoints: impl IntoIterator<Item = Entrypoint>) -> Self {
+        self.config.entrypoints.extend(entrypoints);
+        self
+    }
+
+    pub fn auto_create_entrypoints(mut self, auto_create: bool) -> Self {
+        self.config.auto_create_entrypoints = auto_create;
+        self
+    }
+
+    pub fn api_key(mut self, key: ApiKey) -> Self {
+        self.config.api_keys.push(key);
+        self
+    }
+
+    #[cfg(feature = "crypto")]
+    pub fn crypto(mut self, crypto: CryptoConfig) -> Self {
+        self.config.crypto = crypto;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Load the schemas, replay the journal and create the routes,
+    /// must be called within a tokio runtime. `service` handles the
+    /// entrypoints not bound to a registered one
+    pub fn build(self, service: S) -> io::Result<RsmevServer<S>> {
+        RsmevServer::new(service, self.services, self.config)
+    }
+}
+
+impl<S: Service> Default for RsmevServerBuilder<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,86 +1,466 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use super::api::{RequestStatus, StatusResponse};
 use super::body::Body;
 use super::handler_service::HandlerService;
-use crate::confirm_queue::{ConfirmQueue, KeyGenerator, UuidKey};
-use crate::service::Service;
+use super::journal::{Journal, PendingResponse};
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+use super::status::RequestTracker;
+use super::worker::{self, Task, WorkerConfig};
+use crate::confirm_queue::{ConfirmQueue, Confirmation, KeyGenerator, QueueStats, UuidKey};
+use crate::service::{Message, Service};
 
 use dashmap::DashMap;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
 use uuid::Uuid;
 
-type ChannelTransferType = (Option<NodeId>, Uuid, Body);
-type Queue<T> = ConfirmQueue<T, QUEUE_TTL, UuidKey>;
+pub(crate) type Queue<T> = ConfirmQueue<T, UuidKey>;
 type QueueKey = Uuid;
 
-const CHANNEL_BUFFER_SIZE: usize = 256;
-const QUEUE_TTL: u64 = 1 * 10 * 1000;
+/// Handled response with the ids it keeps across the redeliveries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QueuedResponse {
+    // the defaults fill in the responses journaled without the ids
+    #[serde(default = "Uuid::new_v4")]
+    pub message_id: Uuid,
+    #[serde(default = "Uuid::new_v4")]
+    pub rec_id: Uuid,
+    /// Milliseconds since the unix epoch
+    #[serde(default = "now_ms")]
+    pub queued_at_ms: u64,
+    /// Fault of the service
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fault: bool,
+    pub body: Body,
+}
+
+impl QueuedResponse {
+    /// `Err` with a fault of the service
+    pub fn new(result: Result<Body, Body>) -> Self {
+        Self {
+            message_id: Uuid::new_v4(),
+            rec_id: Uuid::new_v4(),
+            queued_at_ms: now_ms(),
+            fault: result.is_err(),
+            body: result.unwrap_or_else(|fault| fault),
+        }
+    }
+}
+
+/// Response taken by the node
+pub(crate) struct Delivery {
+    pub request_id: Uuid,
+    pub response: QueuedResponse,
+    /// 1 for the first delivery, counted from the startup
+    pub attempt: u32,
+    pub first_delivered_at_ms: u64,
+}
+
+pub(crate) fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
 
-pub struct Client {
-    nodes: Arc<Nodes<Body>>,
-    tx: mpsc::Sender<ChannelTransferType>,
+fn now_ms() -> u64 {
+    unix_ms(SystemTime::now())
 }
 
-const BASE_NODE_ID: &str = "master";
+pub struct Client<S: Service> {
+    entrypoint_id: Uuid,
+    nodes: Arc<Nodes<QueuedResponse>>,
+    tx: mpsc::Sender<Task<S::Request>>,
+    tracker: Arc<RequestTracker>,
+    journal: Option<Arc<Journal>>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<Metrics>,
+    worker: JoinHandle<()>,
+}
+
+impl<S: Service> Client<S> {
+    pub fn new(
+        entrypoint_id: Uuid,
+        service: Arc<HandlerService<S>>,
+        journal: Option<Arc<Journal>>,
+        queue_ttl: Duration,
+        max_deliveries: Option<u32>,
+        workers: WorkerConfig,
+        #[cfg(feature = "metrics")] metrics: Arc<Metrics>,
+    ) -> Self {
+        // requests wait in the worker buffer, the channel only hands them over
+        let (tx, rx) = mpsc::channel(1);
+        let nodes = Arc::new(Nodes::new(queue_ttl, max_deliveries));
+        let tracker = Arc::new(RequestTracker::default());
+
+        let worker = tokio::spawn(worker::run(
+            entrypoint_id,
+            service,
+            workers,
+            tracker.clone(),
+            #[cfg(feature = "metrics")]
+            metrics.clone(),
+            rx,
+            Self::deliver(
+                entrypoint_id,
+                nodes.clone(),
+                tracker.clone(),
+                journal.clone(),
+            ),
+        ));
+        Self {
+            entrypoint_id,
+            nodes,
+            tx,
+            tracker,
+            journal,
+            #[cfg(feature = "metrics")]
+            metrics,
+            worker,
+        }
+    }
 
-impl Client {
-    pub fn new<S: Service>(service: Arc<HandlerService<S>>) -> Self {
-        let (tx, rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-        let nodes = Arc::new(Nodes::new());
+    /// Journal the handled response and queue it for the node
+    fn deliver(
+        entrypoint_id: Uuid,
+        nodes: Arc<Nodes<QueuedResponse>>,
+        tracker: Arc<RequestTracker>,
+        journal: Option<Arc<Journal>>,
+    ) -> impl FnMut(NodeId, QueueKey, Result<Body, Body>) {
+        move |node_id, key, result| {
+            let response = QueuedResponse::new(result);
+            if let Some(journal) = &journal {
+                let pending = PendingResponse {
+                    entrypoint_id,
+                    node_id: node_id.clone(),
+                    request_id: key,
+                    response: response.clone(),
+                    dead_letter: false,
+                };
+                if let Err(e) = journal.push(pending) {
+                    tracing::error!(request_id = %key, error = %e, "failed to journal response");
+                }
+            }
 
-        Self::spawn_handler(service, nodes.clone(), rx);
-        Self { nodes, tx }
+            // before the node can take the response
+            tracker.handled(key, response.fault);
+            nodes.node(node_id).add_with_key(key, response);
+        }
     }
 
-    pub async fn push_task(&self, node_id: Option<NodeId>, body: Body) -> QueueKey {
-        let key = UuidKey::generate();
-        // TODO: throw the error up
-        let _ = self.tx.send((node_id, key, body)).await;
+    /// Close the channel and wait until the queued requests are handled
+    pub async fn shutdown(self) {
+        drop(self.tx);
 
-        key
+        if let Err(e) = self.worker.await {
+            tracing::error!(entrypoint_id = %self.entrypoint_id, error = %e, "client worker failed");
+        }
     }
 
-    pub async fn pop_task(&self, node_id: Option<NodeId>) -> Option<(QueueKey, Body)> {
-        self.nodes
-            .node(node_id)
-            .take()
-            .map(|(id, result)| (*id, result.clone()))
+    /// Put back a response replayed from the journal
+    pub fn restore(
+        &self,
+        node_id: NodeId,
+        key: QueueKey,
+        response: QueuedResponse,
+        dead_letter: bool,
+    ) {
+        let status = if dead_letter {
+            RequestStatus::DeadLetter
+        } else {
+            RequestStatus::ResponseReady
+        };
+        self.tracker
+            .restored(key, node_id.clone(), status, response.fault);
+
+        let mut queue = self.nodes.node(node_id);
+        if dead_letter {
+            queue.add_dead_letter(key, response);
+        } else {
+            queue.add_with_key(key, response);
+        }
+    }
+
+    /// Sender of the requests, it outlives the lock of the clients map
+    pub fn sender(&self) -> TaskSender<S::Request> {
+        TaskSender {
+            tx: self.tx.clone(),
+            tracker: self.tracker.clone(),
+        }
+    }
+
+    pub fn pop_task(&self, node_id: NodeId) -> Option<Delivery> {
+        let mut queue = self.nodes.node(node_id);
+        #[cfg(feature = "metrics")]
+        let redeliveries = queue.redeliveries();
+
+        let task = queue.take_item().map(|(id, item)| Delivery {
+            request_id: *id,
+            response: item.value().clone(),
+            attempt: item.deliveries(),
+            first_delivered_at_ms: item.first_taken().map_or(0, unix_ms),
+        });
+
+        if let Some(delivery) = &task {
+            self.tracker
+                .set(delivery.request_id, RequestStatus::Delivered);
+        }
+
+        for key in queue.take_dead_lettered() {
+            self.tracker.set(key, RequestStatus::DeadLetter);
+            tracing::warn!(
+                entrypoint_id = %self.entrypoint_id,
+                node_id = queue.key(),
+                request_id = %key,
+                "response moved to the dead-letter queue"
+            );
+            if let Some(journal) = &self.journal {
+                if let Err(e) = journal.dead_letter(self.entrypoint_id, queue.key(), key) {
+                    tracing::error!(request_id = %key, error = %e, "failed to journal dead letter");
+                }
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        self.metrics.redelivered(
+            self.entrypoint_id,
+            queue.key(),
+            queue.redeliveries() - redeliveries,
+        );
+
+        task
     }
 
     // TODO: remove files after confirm
-    pub async fn confirm_task(&self, node_id: Option<NodeId>, task_id: &QueueKey) {
-        self.nodes.node(node_id).confirm(task_id);
+    pub fn confirm_task(&self, node_id: NodeId, task_id: &QueueKey) -> Confirmation {
+        let confirmation = self.nodes.node(node_id.clone()).confirm(task_id);
+        if confirmation != Confirmation::Confirmed {
+            return confirmation;
+        }
+
+        self.tracker.set(*task_id, RequestStatus::Confirmed);
+        #[cfg(feature = "metrics")]
+        self.metrics.confirmed(self.entrypoint_id, &node_id);
+
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.confirm(self.entrypoint_id, &node_id, *task_id) {
+                tracing::error!(request_id = %task_id, error = %e, "failed to journal confirmation");
+            }
+        }
+
+        confirmation
     }
 
-    fn spawn_handler<S: Service>(
-        service: Arc<HandlerService<S>>,
-        nodes: Arc<Nodes<Body>>,
-        mut rx: mpsc::Receiver<ChannelTransferType>,
-    ) {
-        tokio::spawn(async move {
-            while let Some((node_id, key, request)) = rx.recv().await {
-                let response = service.handle(request).await;
-                nodes.node(node_id).add_with_key(key, response);
+    /// Requests waiting for the service
+    pub fn queued(&self) -> usize {
+        self.tracker.waiting()
+    }
+
+    pub fn status(&self, task_id: &QueueKey) -> Option<StatusResponse> {
+        self.tracker.get(task_id)
+    }
+
+    pub fn node_stats(&self) -> Vec<(NodeId, QueueStats)> {
+        self.nodes
+            .inner
+            .iter()
+            .map(|node| (node.key().clone(), node.stats()))
+            .collect()
+    }
+
+    /// Access the queue of the node, `None` if the node has no queue yet
+    pub fn inspect<R>(
+        &self,
+        node_id: &str,
+        f: impl FnOnce(&mut Queue<QueuedResponse>) -> R,
+    ) -> Option<R> {
+        self.nodes
+            .inner
+            .get_mut(node_id)
+            .map(|mut queue| f(&mut queue))
+    }
+
+    /// Drop the response without a confirmation, it is not restored from the journal
+    pub fn remove(&self, node_id: &str, task_id: &QueueKey) -> bool {
+        if self
+            .inspect(node_id, |queue| queue.remove(task_id))
+            .flatten()
+            .is_none()
+        {
+            return false;
+        }
+        self.tracker.remove(task_id);
+
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.confirm(self.entrypoint_id, node_id, *task_id) {
+                tracing::error!(request_id = %task_id, error = %e, "failed to journal removal");
             }
-        });
+        }
+
+        true
+    }
+
+    /// Make the response the next one the node takes, even if it is taken
+    pub fn redeliver(&self, node_id: &str, task_id: &QueueKey) -> bool {
+        if !self
+            .inspect(node_id, |queue| queue.redeliver(task_id))
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        self.tracker.set(*task_id, RequestStatus::ResponseReady);
+        true
+    }
+
+    /// Put the dead letter back to the queue of the node
+    pub fn requeue_dead_letter(&self, node_id: &str, task_id: &QueueKey) -> bool {
+        if !self
+            .inspect(node_id, |queue| queue.requeue_dead_letter(task_id))
+            .unwrap_or(false)
+        {
+            return false;
+        }
+        self.tracker.set(*task_id, RequestStatus::ResponseReady);
+
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.requeue(self.entrypoint_id, node_id, *task_id) {
+                tracing::error!(request_id = %task_id, error = %e, "failed to journal requeue");
+            }
+        }
+
+        true
+    }
+
+    /// Drop the dead letter, it is not restored from the journal
+    pub fn remove_dead_letter(&self, node_id: &str, task_id: &QueueKey) -> bool {
+        if self
+            .inspect(node_id, |queue| queue.remove_dead_letter(task_id))
+            .flatten()
+            .is_none()
+        {
+            return false;
+        }
+        self.tracker.remove(task_id);
+
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.confirm(self.entrypoint_id, node_id, *task_id) {
+                tracing::error!(request_id = %task_id, error = %e, "failed to journal removal");
+            }
+        }
+
+        true
+    }
+}
+
+/// Request was not handed over to the worker
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SendError {
+    /// buffer of the entrypoint is full
+    Full,
+    /// worker of the entrypoint is stopped
+    Closed,
+}
+
+pub(crate) struct TaskSender<R> {
+    tx: mpsc::Sender<Task<R>>,
+    tracker: Arc<RequestTracker>,
+}
+
+impl<R> TaskSender<R> {
+    /// Queue the request for the service, waiting up to `timeout` for room
+    /// in a full buffer. The request is rejected at once when it is `None`
+    pub async fn send(
+        &self,
+        node_id: NodeId,
+        message: Message<R>,
+        timeout: Option<Duration>,
+    ) -> Result<QueueKey, SendError> {
+        let permit = match timeout {
+            None => self.tx.try_reserve().map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => SendError::Full,
+                mpsc::error::TrySendError::Closed(_) => SendError::Closed,
+            })?,
+            Some(timeout) => tokio::time::timeout(timeout, self.tx.reserve())
+                .await
+                .map_err(|_| SendError::Full)?
+                .map_err(|_| SendError::Closed)?,
+        };
+
+        let key = UuidKey::generate();
+        self.tracker.queued(key, node_id.clone());
+        permit.send((node_id, key, message));
+
+        Ok(key)
     }
 }
 
-type NodeId = String;
+pub(crate) type NodeId = String;
 struct Nodes<T> {
     inner: DashMap<NodeId, Queue<T>>,
+    ttl: Duration,
+    max_deliveries: Option<u32>,
 }
 
 impl<T> Nodes<T> {
-    pub fn new() -> Self {
+    pub fn new(ttl: Duration, max_deliveries: Option<u32>) -> Self {
         Nodes {
             inner: DashMap::new(),
+            ttl,
+            max_deliveries,
         }
     }
 
-    pub fn node(&self, name: Option<String>) -> dashmap::mapref::one::RefMut<'_, NodeId, Queue<T>> {
+    pub fn node(&self, name: NodeId) -> dashmap::mapref::one::RefMut<'_, NodeId, Queue<T>> {
         self.inner
-            .entry(name.unwrap_or(BASE_NODE_ID.to_string()))
-            .or_insert(Queue::new())
+            .entry(name)
+            .or_insert_with(|| Queue::new(self.ttl).with_max_deliveries(self.max_deliveries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SendError, TaskSender};
+    use crate::service::Message;
+
+    use crate::server::status::RequestTracker;
+
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    pub async fn test_send_backpressure() {
+        let (tx, rx) = mpsc::channel(1);
+        let sender = TaskSender {
+            tx,
+            tracker: Arc::new(RequestTracker::default()),
+        };
+        let message = || Message {
+            content: (),
+            files: Vec::new(),
+        };
+        let node_id = || "master".to_string();
+
+        assert!(sender.send(node_id(), message(), None).await.is_ok());
+        assert_eq!(
+            sender.send(node_id(), message(), None).await,
+            Err(SendError::Full)
+        );
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(
+            sender.send(node_id(), message(), timeout).await,
+            Err(SendError::Full)
+        );
+
+        drop(rx);
+        assert_eq!(
+            sender.send(node_id(), message(), timeout).await,
+            Err(SendError::Closed)
+        );
     }
 }
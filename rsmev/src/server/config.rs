@@ -0,0 +1,97 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use super::auth::ApiKey;
+use super::entrypoint::Entrypoint;
+#[cfg(feature = "tls")]
+use super::tls::TlsConfig;
+#[cfg(feature = "crypto")]
+use crate::crypto::CryptoConfig;
+use crate::file_store::{FileStore, LocalFileStore};
+
+pub const DEFAULT_QUEUE_TTL: Duration = Duration::from_secs(10);
+pub const DEFAULT_CHANNEL_BUFFER_SIZE: usize = 256;
+pub const DEFAULT_CONCURRENCY: usize = 1;
+pub const DEFAULT_NODE_ID: &str = "master";
+pub const DEFAULT_NODE_ID_HEADER: &str = "node_id";
+pub const DEFAULT_PATH_PREFIX: &str = "/api/smev";
+
+/// Startup configuration of the SMEV adapter mock
+pub struct ServeConfig {
+    pub file_store: Arc<dyn FileStore>,
+    /// Journal file of the not confirmed responses, replayed on startup.
+    /// Responses are kept in memory only when it is `None`.
+    ///
+    /// Only the handled requests are journaled: a request accepted by
+    /// `sendrequest` is lost on a crash until the service has handled it,
+    /// the client finds it out by the `404` of `/status/:request_id`
+    pub journal: Option<PathBuf>,
+    /// Time a taken response waits for the confirmation before it is redelivered
+    pub queue_ttl: Duration,
+    /// Deliveries of a response without a confirmation before it is moved to
+    /// the dead-letter queue of the node, it is redelivered forever when `None`.
+    ///
+    /// Deliveries are counted in memory only, a response replayed from the
+    /// `journal` starts over from the first delivery after a restart
+    pub max_deliveries: Option<u32>,
+    /// Requests waiting for the service, per entrypoint, at least one
+    pub channel_buffer_size: usize,
+    /// Time `sendrequest` waits for room in a full buffer before it is
+    /// rejected with 429, it is rejected at once when `None`
+    pub send_timeout: Option<Duration>,
+    /// Requests of an entrypoint handled at once
+    pub concurrency: usize,
+    /// Requests of a node handled at once, limited by `concurrency` only when `None`
+    pub node_concurrency: Option<usize>,
+    /// Queue the responses of a node in the order of its requests, even if
+    /// a later request is handled first
+    pub preserve_node_order: bool,
+    /// Node of the requests without the node id header
+    pub default_node_id: String,
+    /// Header with the node id
+    pub node_id_header: String,
+    /// Routes are served under `<path_prefix>/:entrypoint_id`
+    pub path_prefix: String,
+    /// Admin routes are served under the prefix when it is set, see
+    /// `RsmevServer::admin_router` to serve them on another port
+    pub admin_path_prefix: Option<String>,
+    /// Registered entrypoints
+    pub entrypoints: Vec<Entrypoint>,
+    /// Create an entrypoint on the first request to an unknown id,
+    /// otherwise such requests are rejected with 404. Disabled by default,
+    /// only the registered `entrypoints` are served
+    pub auto_create_entrypoints: bool,
+    /// Keys accepted by the API, authentication is disabled when empty
+    pub api_keys: Vec<ApiKey>,
+    #[cfg(feature = "crypto")]
+    pub crypto: CryptoConfig,
+    /// Serve HTTPS instead of plain HTTP
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        Self {
+            file_store: Arc::new(LocalFileStore::default()),
+            journal: None,
+            queue_ttl: DEFAULT_QUEUE_TTL,
+            max_deliveries: None,
+            channel_buffer_size: DEFAULT_CHANNEL_BUFFER_SIZE,
+            send_timeout: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            node_concurrency: None,
+            preserve_node_order: false,
+            default_node_id: DEFAULT_NODE_ID.to_string(),
+            node_id_header: DEFAULT_NODE_ID_HEADER.to_string(),
+            path_prefix: DEFAULT_PATH_PREFIX.to_string(),
+            admin_path_prefix: None,
+            entrypoints: Vec::new(),
+            auto_create_entrypoints: false,
+            api_keys: Vec::new(),
+            #[cfg(feature = "crypto")]
+            crypto: CryptoConfig::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::{collections::HashMap, io, path::Path, time::Duration};
+
+use serde::{Deserialize, Deserializer};
+use uuid::Uuid;
+
+use super::error::ApiError;
+
+/// Known entrypoint of the adapter, `/api/smev/:entrypoint_id`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entrypoint {
+    pub id: Uuid,
+    pub name: String,
+    /// Overrides `ServeConfig::queue_ttl`
+    #[serde(default, rename = "queueTtlMs", deserialize_with = "millis")]
+    pub queue_ttl: Option<Duration>,
+    /// Overrides `ServeConfig::concurrency`
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// Node ids allowed to use the entrypoint, any node when empty
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    /// Service registered by `RsmevServerBuilder::service`, the service
+    /// passed to `build` when `None`
+    #[serde(default)]
+    pub service: Option<String>,
+}
+
+impl Entrypoint {
+    pub fn new(id: Uuid, name: impl Into<String>) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            queue_ttl: None,
+            concurrency: None,
+            nodes: Vec::new(),
+            service: None,
+        }
+    }
+
+    /// Handle the requests by the named service
+    pub fn with_service(mut self, name: impl Into<String>) -> Self {
+        self.service = Some(name.into());
+        self
+    }
+
+    pub fn allows_node(&self, node_id: &str) -> bool {
+        self.nodes.is_empty() || self.nodes.iter().any(|n| n == node_id)
+    }
+}
+
+/// Registered entrypoints, looked up on every request
+pub(crate) struct Registry {
+    entrypoints: HashMap<Uuid, Entrypoint>,
+    auto_create: bool,
+}
+
+impl Registry {
+    pub fn new(entrypoints: Vec<Entrypoint>, auto_create: bool) -> Self {
+        Self {
+            entrypoints: entrypoints.into_iter().map(|e| (e.id, e)).collect(),
+            auto_create,
+        }
+    }
+
+    /// Check the entrypoint and the node, `None` is an auto-created entrypoint
+    pub fn get(&self, id: Uuid, node_id: &str) -> Result<Option<&Entrypoint>, ApiError> {
+        match self.entrypoints.get(&id) {
+            Some(entrypoint) if !entrypoint.allows_node(node_id) => {
+                Err(ApiError::UnknownNode(node_id.to_string()))
+            }
+            Some(entrypoint) => Ok(Some(entrypoint)),
+            None if self.auto_create => Ok(None),
+            None => Err(ApiError::UnknownEntrypoint(id)),
+        }
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.entrypoints.keys().copied()
+    }
+
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.entrypoints.contains_key(&id)
+    }
+
+    pub fn name(&self, id: Uuid) -> Option<&str> {
+        self.entrypoints.get(&id).map(|e| e.name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entrypoint> {
+        self.entrypoints.values()
+    }
+}
+
+/// Read a json array of the entrypoints
+pub fn read_entrypoints(path: impl AsRef<Path>) -> io::Result<Vec<Entrypoint>> {
+    let content = std::fs::read(path)?;
+
+    Ok(serde_json::from_slice(&content)?)
+}
+
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Entrypoint;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_deserialize() {
+        let entrypoints: Vec<Entrypoint> = serde_json::from_str(
+            r#"[
+                {"id": "6f2b0c2e-8f57-4b8e-9d1a-0c5e1f3a7b21", "name": "pos", "queueTtlMs": 500, "concurrency": 4, "nodes": ["n1"], "service": "pos"},
+                {"id": "0b8d4a55-2d6c-4b0e-a0c4-7f1e9b3c5d42", "name": "other"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(entrypoints[0].queue_ttl, Some(Duration::from_millis(500)));
+        assert_eq!(entrypoints[0].concurrency, Some(4));
+        assert!(entrypoints[0].allows_node("n1"));
+        assert!(!entrypoints[0].allows_node("master"));
+        assert_eq!(entrypoints[0].service.as_deref(), Some("pos"));
+
+        assert_eq!(entrypoints[1].queue_ttl, None);
+        assert!(entrypoints[1].allows_node("master"));
+        assert_eq!(entrypoints[1].service, None);
+    }
+}
@@ -0,0 +1,159 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use uuid::Uuid;
+
+use super::{api::ErrorResponse, body, handler_service};
+
+/// Seconds a rejected request should wait before it is sent again
+const RETRY_AFTER_SECS: &str = "1";
+
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    InvalidRequest(handler_service::Error),
+    /// credential is missing or unknown
+    Unauthorized,
+    /// key is not allowed to use the entrypoint
+    Forbidden(Uuid),
+    /// key is restricted to some entrypoints and cannot use the admin api
+    AdminForbidden,
+    UnknownEntrypoint(Uuid),
+    /// node is not allowed to use the entrypoint
+    UnknownNode(String),
+    /// request id that was never queued, e.g. on a confirmation
+    UnknownRequest(Uuid),
+    /// request was moved to the dead-letter queue before the confirmation
+    DeadLetter(Uuid),
+    /// buffer of the entrypoint is full
+    QueueFull(Uuid),
+    /// worker of the entrypoint is stopped
+    EntrypointUnavailable(Uuid),
+    /// server is shutting down and accepts no new requests
+    ShuttingDown,
+    /// service readiness check failed
+    NotReady(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) | ApiError::AdminForbidden => StatusCode::FORBIDDEN,
+            ApiError::UnknownEntrypoint(_)
+            | ApiError::UnknownNode(_)
+            | ApiError::UnknownRequest(_) => StatusCode::NOT_FOUND,
+            ApiError::DeadLetter(_) => StatusCode::GONE,
+            ApiError::QueueFull(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::EntrypointUnavailable(_) | ApiError::ShuttingDown | ApiError::NotReady(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        use handler_service::Error as RequestError;
+
+        match self {
+            ApiError::InvalidRequest(RequestError::Xml(e)) => match e {
+                body::Error::Base64(_) => "INVALID_BASE64",
+                body::Error::Utf8(_) => "INVALID_UTF8",
+                body::Error::Deserialize(_) | body::Error::Serialize(_) => "INVALID_XML",
+            },
+            ApiError::InvalidRequest(RequestError::MissingFile(_)) => "MISSING_FILE",
+            ApiError::InvalidRequest(RequestError::InvalidFile(..)) => "INVALID_FILE",
+            #[cfg(feature = "crypto")]
+            ApiError::InvalidRequest(RequestError::Signature(_, e)) => match e {
+                crate::crypto::pkcs7::SignatureError::Missing => "MISSING_SIGNATURE",
+                _ => "INVALID_SIGNATURE",
+            },
+            #[cfg(feature = "crypto")]
+            ApiError::InvalidRequest(RequestError::XmlSignature(e)) => match e {
+                crate::crypto::xmldsig::XmlSignatureError::Missing => "MISSING_XML_SIGNATURE",
+                _ => "INVALID_XML_SIGNATURE",
+            },
+            #[cfg(feature = "xsd")]
+            ApiError::InvalidRequest(RequestError::Schema(_)) => "SCHEMA_VALIDATION_FAILED",
+            ApiError::Unauthorized => "UNAUTHORIZED",
+            ApiError::Forbidden(_) | ApiError::AdminForbidden => "FORBIDDEN",
+            ApiError::UnknownEntrypoint(_) => "UNKNOWN_ENTRYPOINT",
+            ApiError::UnknownNode(_) => "UNKNOWN_NODE",
+            ApiError::UnknownRequest(_) => "UNKNOWN_REQUEST",
+            ApiError::DeadLetter(_) => "DEAD_LETTER",
+            ApiError::QueueFull(_) => "QUEUE_FULL",
+            ApiError::EntrypointUnavailable(_) => "ENTRYPOINT_UNAVAILABLE",
+            ApiError::ShuttingDown => "SHUTTING_DOWN",
+            ApiError::NotReady(_) => "NOT_READY",
+        }
+    }
+
+    /// Every validation error, so the client sees all of them at once
+    fn details(&self) -> Vec<String> {
+        match self {
+            #[cfg(feature = "xsd")]
+            ApiError::InvalidRequest(handler_service::Error::Schema(errors)) => errors.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::InvalidRequest(e) => e.fmt(f),
+            ApiError::Unauthorized => write!(f, "api key is missing or unknown"),
+            ApiError::Forbidden(id) => write!(f, "access to entrypoint `{id}` is forbidden"),
+            ApiError::AdminForbidden => write!(f, "access to the admin api is forbidden"),
+            ApiError::UnknownEntrypoint(id) => write!(f, "entrypoint `{id}` is unknown"),
+            ApiError::UnknownNode(node) => write!(f, "node `{node}` is unknown"),
+            ApiError::UnknownRequest(id) => write!(f, "request `{id}` is unknown"),
+            ApiError::DeadLetter(id) => {
+                write!(f, "request `{id}` was moved to the dead-letter queue")
+            }
+            ApiError::QueueFull(id) => write!(f, "requests of entrypoint `{id}` are queued up"),
+            ApiError::EntrypointUnavailable(id) => write!(f, "entrypoint `{id}` is unavailable"),
+            ApiError::ShuttingDown => write!(f, "server is shutting down"),
+            ApiError::NotReady(reason) => write!(f, "service is not ready: {reason}"),
+        }
+    }
+}
+
+impl From<handler_service::Error> for ApiError {
+    fn from(value: handler_service::Error) -> Self {
+        ApiError::InvalidRequest(value)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        tracing::warn!(error = %self, "request rejected");
+
+        let body = ErrorResponse {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            details: self.details(),
+        };
+
+        let mut response = (self.status(), Json(body)).into_response();
+        match self {
+            ApiError::Unauthorized => {
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static("Bearer"),
+                );
+            }
+            ApiError::QueueFull(_) | ApiError::EntrypointUnavailable(_) => {
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    header::HeaderValue::from_static(RETRY_AFTER_SECS),
+                );
+            }
+            _ => {}
+        }
+
+        response
+    }
+}
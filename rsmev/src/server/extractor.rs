@@ -1,10 +1,15 @@
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
+    http::{request::Parts, HeaderName, StatusCode},
 };
 
-const NODE_ID_HEADER_NAME: &str = "node_id";
+use super::config::DEFAULT_NODE_ID_HEADER;
+
+/// Name of the node id header, a request extension set by the router
+#[derive(Debug, Clone)]
+pub(crate) struct NodeIdHeader(pub HeaderName);
+
 pub struct HeaderNodeId(pub Option<String>);
 
 #[async_trait]
@@ -15,10 +20,15 @@ where
     type Rejection = (StatusCode, &'static str);
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        if let Some(header) = parts.headers.get(NODE_ID_HEADER_NAME) {
+        let name = match parts.extensions.get::<NodeIdHeader>() {
+            Some(NodeIdHeader(name)) => name.clone(),
+            None => HeaderName::from_static(DEFAULT_NODE_ID_HEADER),
+        };
+
+        if let Some(header) = parts.headers.get(name) {
             let value = header
                 .to_str()
-                .map_err(|_| (StatusCode::BAD_REQUEST, "`node_id` header is not a string"))?;
+                .map_err(|_| (StatusCode::BAD_REQUEST, "node id header is not a string"))?;
             Ok(HeaderNodeId(Some(value.to_string())))
         } else {
             Ok(HeaderNodeId(None))
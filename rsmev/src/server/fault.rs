@@ -0,0 +1,64 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::body::EncodedXml;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum FaultCode {
+    /// `Service::handle` returned an error
+    #[serde(rename = "SERVICE_ERROR")]
+    Service,
+    /// response files could not be published to the file store
+    #[serde(rename = "FILE_ERROR")]
+    File,
+    /// response could not be serialized to xml
+    #[serde(rename = "SERIALIZATION_ERROR")]
+    Serialization,
+    /// response files could not be signed
+    #[cfg(feature = "crypto")]
+    #[serde(rename = "SIGNATURE_ERROR")]
+    Signature,
+}
+
+/// SMEV fault payload, sent to the node instead of a service response
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename = "Fault", rename_all = "PascalCase")]
+pub struct Fault {
+    pub code: FaultCode,
+    pub description: String,
+    pub request_id: Uuid,
+}
+
+impl Fault {
+    pub fn new(code: FaultCode, description: impl Into<String>, request_id: Uuid) -> Self {
+        Self {
+            code,
+            description: description.into(),
+            request_id,
+        }
+    }
+
+    pub fn to_xml(&self) -> EncodedXml {
+        EncodedXml::serialize(self).expect("fault is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fault, FaultCode};
+
+    #[test]
+    pub fn test_fault_xml() {
+        let request_id = uuid::Uuid::nil();
+        let fault = Fault::new(FaultCode::Service, "no appeals", request_id);
+
+        let xml = quick_xml::se::to_string(&fault).unwrap();
+        assert_eq!(
+            xml,
+            format!(
+                "<Fault><Code>SERVICE_ERROR</Code><Description>no appeals</Description>\
+                 <RequestId>{request_id}</RequestId></Fault>"
+            )
+        );
+    }
+}
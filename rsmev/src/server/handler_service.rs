@@ -1,97 +1,273 @@
-use std::env;
+use std::{io, sync::Arc};
 
 use uuid::Uuid;
 
-use super::body::{Body as RsmevBody, EncodedXml, File as RsmevFile};
+use super::body::{self, Body as RsmevBody, EncodedXml, File as RsmevFile};
+use super::fault::{Fault, FaultCode};
+#[cfg(feature = "crypto")]
+use crate::crypto::{
+    pkcs7::SignatureError,
+    xmldsig::{self, XmlSignatureError},
+    CryptoConfig, SignaturePolicy,
+};
+use crate::file_store::FileStore;
 use crate::service::{Message, Service};
+#[cfg(feature = "xsd")]
+use crate::xsd::SchemaSet;
 
 pub struct HandlerService<S> {
     service: S,
+    files: Arc<dyn FileStore>,
+    #[cfg(feature = "crypto")]
+    crypto: Arc<CryptoConfig>,
+    #[cfg(feature = "xsd")]
+    schemas: SchemaSet,
 }
 
-const BASE_FILE_DIR: &str = "./ftp_data";
+#[derive(Debug)]
+pub enum Error {
+    Xml(body::Error),
+    MissingFile(String),
+    InvalidFile(String, io::Error),
+    #[cfg(feature = "crypto")]
+    Signature(String, SignatureError),
+    #[cfg(feature = "crypto")]
+    XmlSignature(XmlSignatureError),
+    #[cfg(feature = "xsd")]
+    Schema(Vec<String>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Xml(e) => e.fmt(f),
+            Error::MissingFile(url) => write!(f, "attachment file `{url}` does not exist"),
+            Error::InvalidFile(url, e) => write!(f, "attachment file `{url}` is invalid: {e}"),
+            #[cfg(feature = "crypto")]
+            Error::Signature(url, e) => write!(f, "attachment file `{url}`: {e}"),
+            #[cfg(feature = "crypto")]
+            Error::XmlSignature(e) => e.fmt(f),
+            #[cfg(feature = "xsd")]
+            Error::Schema(errors) => {
+                write!(f, "xml does not match the schema: {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<body::Error> for Error {
+    fn from(value: body::Error) -> Self {
+        Error::Xml(value)
+    }
+}
 
 impl<S: Service> HandlerService<S> {
-    pub fn new(service: S) -> Self {
-        Self { service }
+    pub fn new(service: S, files: Arc<dyn FileStore>) -> Self {
+        Self {
+            service,
+            files,
+            #[cfg(feature = "crypto")]
+            crypto: Arc::default(),
+            #[cfg(feature = "xsd")]
+            schemas: SchemaSet::default(),
+        }
     }
 
-    pub async fn handle(&self, body: RsmevBody) -> RsmevBody {
-        let content = Self::to_message(body);
+    #[cfg(feature = "crypto")]
+    pub fn with_crypto(mut self, crypto: Arc<CryptoConfig>) -> Self {
+        self.crypto = crypto;
+        self
+    }
 
+    #[cfg(feature = "xsd")]
+    pub fn with_schemas(mut self, schemas: SchemaSet) -> Self {
+        self.schemas = schemas;
+        self
+    }
+
+    /// `Err` is the fault sent back when the service failed
+    pub async fn handle(
+        &self,
+        request_id: Uuid,
+        content: Message<S::Request>,
+    ) -> Result<RsmevBody, RsmevBody> {
         let response = self.service.handle(content).await;
 
-        Self::to_rsmev_body(response)
+        self.to_rsmev_body(request_id, response)
     }
 
-    pub(crate) fn to_message(body: RsmevBody) -> Message<S::Request> {
-        let current_dir = env::current_dir().unwrap();
+    pub async fn ready(&self) -> Result<(), S::Error> {
+        self.service.ready().await
+    }
 
+    pub(crate) fn to_message(&self, body: RsmevBody) -> Result<Message<S::Request>, Error> {
         let RsmevBody { files, xml } = body;
 
         let files = files
             .into_iter()
             .map(|f| {
-                let mut file_path = current_dir.clone();
-                file_path.push(BASE_FILE_DIR);
-                file_path.push(f.url);
-
-                if std::fs::metadata(&file_path).is_ok() {
-                    Ok(file_path)
-                } else {
-                    Err(())
-                }
+                let path = match self.files.resolve(&f.url) {
+                    Ok(path) => path,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        return Err(Error::MissingFile(f.url))
+                    }
+                    Err(e) => return Err(Error::InvalidFile(f.url, e)),
+                };
+
+                #[cfg(feature = "crypto")]
+                self.verify_signature(&f)?;
+
+                Ok(path)
             })
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let xml = self.unsigned_xml(&xml)?;
+        #[cfg(feature = "xsd")]
+        self.schemas.validate(&xml).map_err(Error::Schema)?;
 
-        Message {
-            content: xml.deserialize().unwrap(),
+        Ok(Message {
+            content: EncodedXml::deserialize_str(&xml)?,
             files,
-        }
+        })
     }
 
-    pub(crate) fn to_rsmev_body(message: Result<Message<S::Response>, S::Error>) -> RsmevBody {
-        fn ok<R: serde::Serialize>(message: Message<R>) -> RsmevBody {
-            let current_dir = env::current_dir().unwrap();
+    /// Decoded xml with the signature verified and removed
+    #[cfg(feature = "crypto")]
+    fn unsigned_xml(&self, xml: &EncodedXml) -> Result<String, Error> {
+        let xml = xml.decode()?;
+        let Some(verifier) = &self.crypto.xml_verifier else {
+            return Ok(xml);
+        };
 
-            let Message { content, files } = message;
+        match verifier.verify(&xml) {
+            Ok(unsigned) => Ok(unsigned),
+            Err(e) if verifier.policy() == SignaturePolicy::Warn => {
+                tracing::warn!(error = %e, "xml signature is not valid");
+                Ok(xmldsig::strip_signature(&xml))
+            }
+            Err(e) => Err(Error::XmlSignature(e)),
+        }
+    }
 
-            let files = files
-                .into_iter()
-                .map(|file| {
-                    let mut new_path = current_dir.clone();
-                    new_path.push(BASE_FILE_DIR);
+    #[cfg(not(feature = "crypto"))]
+    fn unsigned_xml(&self, xml: &EncodedXml) -> Result<String, Error> {
+        Ok(xml.decode()?)
+    }
 
-                    let path_id = Uuid::new_v4().to_string();
-                    new_path.push(&path_id);
+    #[cfg(feature = "crypto")]
+    fn verify_signature(&self, file: &RsmevFile) -> Result<(), Error> {
+        let Some(verifier) = &self.crypto.attachment_verifier else {
+            return Ok(());
+        };
 
-                    std::fs::create_dir(&new_path).unwrap();
-                    let file_name = file.file_name().unwrap().to_string_lossy().to_string();
-                    new_path.push(&file_name);
+        let content = self
+            .files
+            .read(&file.url)
+            .map_err(|e| Error::InvalidFile(file.url.clone(), e))?;
 
-                    std::fs::rename(file, new_path).unwrap();
-                    RsmevFile {
-                        name: file_name.to_string(),
-                        url: format!("/{path_id}/{file_name}"),
-                        signature: None,
-                    }
-                })
-                .collect::<Vec<_>>();
-
-            RsmevBody {
-                xml: EncodedXml::serialize(&content).unwrap(),
-                files,
+        match verifier.verify(file.signature.as_deref(), &content) {
+            Ok(()) => Ok(()),
+            Err(e) if verifier.policy() == SignaturePolicy::Warn => {
+                tracing::warn!(url = %file.url, error = %e, "attachment signature is not valid");
+                Ok(())
             }
+            Err(e) => Err(Error::Signature(file.url.clone(), e)),
         }
+    }
 
-        fn err<E: std::error::Error>(err: E) -> RsmevBody {
-            unimplemented!()
-        }
+    pub(crate) fn to_rsmev_body(
+        &self,
+        request_id: Uuid,
+        message: Result<Message<S::Response>, S::Error>,
+    ) -> Result<RsmevBody, RsmevBody> {
+        let body = message
+            .map_err(|e| {
+                tracing::warn!(%request_id, error = %e, "service returned an error");
+                Fault::new(FaultCode::Service, e.to_string(), request_id)
+            })
+            .and_then(|m| self.publish(request_id, m));
 
-        match message {
-            Ok(m) => ok(m),
-            Err(e) => err(e),
+        body.map_err(|fault| self.fault(fault))
+    }
+
+    /// Body sent to the node instead of the response
+    pub(crate) fn fault(&self, fault: Fault) -> RsmevBody {
+        RsmevBody {
+            xml: self
+                .serialize(fault.request_id, &fault)
+                .unwrap_or_else(|_| fault.to_xml()),
+            files: Vec::new(),
         }
     }
+
+    fn publish(&self, request_id: Uuid, message: Message<S::Response>) -> Result<RsmevBody, Fault> {
+        let Message { content, files } = message;
+
+        let files = files
+            .into_iter()
+            .map(|file| {
+                let url = self.files.publish(&file).map_err(|e| {
+                    tracing::error!(%request_id, file = %file.display(), error = %e);
+                    Fault::new(FaultCode::File, e.to_string(), request_id)
+                })?;
+                let name = url.rsplit('/').next().unwrap_or_default().to_string();
+
+                Ok(RsmevFile {
+                    signature: self.sign(request_id, &url)?,
+                    name,
+                    url,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let xml = self.serialize(request_id, &content)?;
+
+        Ok(RsmevBody { xml, files })
+    }
+
+    fn serialize<T: serde::Serialize>(
+        &self,
+        request_id: Uuid,
+        content: &T,
+    ) -> Result<EncodedXml, Fault> {
+        let xml = EncodedXml::serialize_to_string(content).map_err(|e| {
+            tracing::error!(%request_id, error = %e);
+            Fault::new(FaultCode::Serialization, e.to_string(), request_id)
+        })?;
+
+        #[cfg(feature = "crypto")]
+        let xml = match &self.crypto.xml_signer {
+            Some(signer) => signer.sign(&xml).map_err(|e| {
+                tracing::error!(%request_id, error = %e, "failed to sign xml");
+                Fault::new(FaultCode::Signature, e.to_string(), request_id)
+            })?,
+            None => xml,
+        };
+
+        Ok(EncodedXml::encode(&xml))
+    }
+
+    #[cfg(feature = "crypto")]
+    fn sign(&self, request_id: Uuid, url: &str) -> Result<Option<String>, Fault> {
+        let Some(signer) = &self.crypto.attachment_signer else {
+            return Ok(None);
+        };
+
+        let content = self.files.read(url).map_err(|e| {
+            tracing::error!(%request_id, url, error = %e);
+            Fault::new(FaultCode::File, e.to_string(), request_id)
+        })?;
+
+        signer.sign(&content).map(Some).map_err(|e| {
+            tracing::error!(%request_id, url, error = %e, "failed to sign attachment");
+            Fault::new(FaultCode::Signature, e.to_string(), request_id)
+        })
+    }
+
+    #[cfg(not(feature = "crypto"))]
+    fn sign(&self, _request_id: Uuid, _url: &str) -> Result<Option<String>, Fault> {
+        Ok(None)
+    }
 }
@@ -0,0 +1,385 @@
+//! Append-only journal of the responses not confirmed by the clients.
+//!
+//! Every line is a json record, on startup the journal is replayed and
+//! compacted to the pending responses only. Records are written by a
+//! dedicated thread, so the async workers never wait for the disk, it also
+//! compacts the journal after every `COMPACT_AFTER` confirmations.
+
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use super::client::QueuedResponse;
+
+/// Confirmations written before the journal is compacted again
+const COMPACT_AFTER: usize = 1_000;
+
+/// Response waiting for a confirmation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PendingResponse {
+    pub entrypoint_id: Uuid,
+    pub node_id: String,
+    pub request_id: Uuid,
+    #[serde(flatten)]
+    pub response: QueuedResponse,
+    /// Moved to the dead-letter queue after too many deliveries
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dead_letter: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum Record {
+    Push(PendingResponse),
+    #[serde(rename_all = "camelCase")]
+    Confirm {
+        entrypoint_id: Uuid,
+        node_id: String,
+        request_id: Uuid,
+    },
+    #[serde(rename_all = "camelCase")]
+    DeadLetter {
+        entrypoint_id: Uuid,
+        node_id: String,
+        request_id: Uuid,
+    },
+    /// Dead letter put back to the queue
+    #[serde(rename_all = "camelCase")]
+    Requeue {
+        entrypoint_id: Uuid,
+        node_id: String,
+        request_id: Uuid,
+    },
+}
+
+enum Command {
+    Write(Record),
+    /// Flush the records written so far to the disk
+    Sync(oneshot::Sender<io::Result<()>>),
+}
+
+pub(crate) struct Journal {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl Journal {
+    /// Open the journal, returns it with the responses to restore
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<PendingResponse>)> {
+        let pending = match File::open(path) {
+            Ok(file) => replay(file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        compact(path, &pending)?;
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let path = path.to_path_buf();
+        thread::Builder::new()
+            .name("rsmev-journal".to_string())
+            .spawn(move || write_records(path, BufWriter::new(file), rx))?;
+
+        Ok((Self { tx }, pending))
+    }
+
+    pub fn push(&self, response: PendingResponse) -> io::Result<()> {
+        self.write(Record::Push(response))
+    }
+
+    pub fn confirm(&self, entrypoint_id: Uuid, node_id: &str, request_id: Uuid) -> io::Result<()> {
+        self.write(Record::Confirm {
+            entrypoint_id,
+            node_id: node_id.to_string(),
+            request_id,
+        })
+    }
+
+    pub fn dead_letter(
+        &self,
+        entrypoint_id: Uuid,
+        node_id: &str,
+        request_id: Uuid,
+    ) -> io::Result<()> {
+        self.write(Record::DeadLetter {
+            entrypoint_id,
+            node_id: node_id.to_string(),
+            request_id,
+        })
+    }
+
+    pub fn requeue(&self, entrypoint_id: Uuid, node_id: &str, request_id: Uuid) -> io::Result<()> {
+        self.write(Record::Requeue {
+            entrypoint_id,
+            node_id: node_id.to_string(),
+            request_id,
+        })
+    }
+
+    /// Wait until the records are written and flushed to the disk
+    pub async fn sync(&self) -> io::Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(Command::Sync(tx)).map_err(|_| stopped())?;
+
+        rx.await.map_err(|_| stopped())?
+    }
+
+    /// Queue the record for the writer, it fails only if the writer is stopped
+    fn write(&self, record: Record) -> io::Result<()> {
+        self.tx.send(Command::Write(record)).map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "journal writer is stopped")
+}
+
+/// Writer thread, runs until every `Journal` handle is dropped
+fn write_records(
+    path: PathBuf,
+    mut file: BufWriter<File>,
+    mut rx: mpsc::UnboundedReceiver<Command>,
+) {
+    let mut confirmed = 0;
+
+    while let Some(command) = rx.blocking_recv() {
+        // the records queued meanwhile go with the same flush
+        let mut next = Some(command);
+        while let Some(command) = next {
+            match command {
+                Command::Write(record) => {
+                    if let Err(e) = write_record(&mut file, &record) {
+                        tracing::error!(error = %e, "failed to write journal record");
+                    }
+
+                    if matches!(record, Record::Confirm { .. }) {
+                        confirmed += 1;
+                    }
+                    if confirmed >= COMPACT_AFTER {
+                        confirmed = 0;
+                        match reopen_compacted(&path, &mut file) {
+                            Ok(compacted) => file = compacted,
+                            Err(e) => tracing::error!(error = %e, "failed to compact journal"),
+                        }
+                    }
+                }
+                Command::Sync(done) => {
+                    let _ = done.send(file.flush().and_then(|_| file.get_ref().sync_all()));
+                }
+            }
+            next = rx.try_recv().ok();
+        }
+
+        if let Err(e) = file.flush() {
+            tracing::error!(error = %e, "failed to flush journal");
+        }
+    }
+}
+
+/// Replace the journal with its pending responses
+fn reopen_compacted(path: &Path, file: &mut BufWriter<File>) -> io::Result<BufWriter<File>> {
+    file.flush()?;
+    let pending = replay(File::open(path)?)?;
+    compact(path, &pending)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+fn write_record(file: &mut impl Write, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(&mut *file, record)?;
+    file.write_all(b"\n")
+}
+
+fn replay(file: File) -> io::Result<Vec<PendingResponse>> {
+    let mut pushed = Vec::new();
+    let mut confirmed = HashSet::new();
+    let mut dead_letters = HashSet::new();
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // the last line may be cut by a crash in the middle of a write
+        match serde_json::from_str(&line) {
+            Ok(Record::Push(response)) => {
+                // a compacted journal keeps the dead letters as flagged pushes
+                if response.dead_letter {
+                    dead_letters.insert((
+                        response.entrypoint_id,
+                        response.node_id.clone(),
+                        response.request_id,
+                    ));
+                }
+                pushed.push(response);
+            }
+            Ok(Record::Confirm {
+                entrypoint_id,
+                node_id,
+                request_id,
+            }) => {
+                confirmed.insert((entrypoint_id, node_id, request_id));
+            }
+            Ok(Record::DeadLetter {
+                entrypoint_id,
+                node_id,
+                request_id,
+            }) => {
+                dead_letters.insert((entrypoint_id, node_id, request_id));
+            }
+            Ok(Record::Requeue {
+                entrypoint_id,
+                node_id,
+                request_id,
+            }) => {
+                dead_letters.remove(&(entrypoint_id, node_id, request_id));
+            }
+            Err(e) => tracing::warn!(line = number + 1, error = %e, "skipped journal record"),
+        }
+    }
+
+    Ok(pushed
+        .into_iter()
+        .filter_map(|mut r| {
+            let key = (r.entrypoint_id, r.node_id.clone(), r.request_id);
+            if confirmed.contains(&key) {
+                return None;
+            }
+
+            r.dead_letter = dead_letters.contains(&key);
+            Some(r)
+        })
+        .collect())
+}
+
+/// Rewrite the journal with the pending responses only
+fn compact(path: &Path, pending: &[PendingResponse]) -> io::Result<()> {
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for response in pending {
+        write_record(&mut writer, &Record::Push(response.clone()))?;
+    }
+    writer.into_inner()?.sync_all()?;
+
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Journal, PendingResponse};
+    use crate::server::body::{Body, EncodedXml};
+    use crate::server::client::QueuedResponse;
+
+    use std::io::Write;
+    use uuid::Uuid;
+
+    fn response(entrypoint_id: Uuid, xml: &str) -> PendingResponse {
+        PendingResponse {
+            entrypoint_id,
+            node_id: "master".to_string(),
+            request_id: Uuid::new_v4(),
+            response: QueuedResponse::new(Ok(Body {
+                xml: EncodedXml::encode(xml),
+                files: Vec::new(),
+            })),
+            dead_letter: false,
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_replay() {
+        let path = std::env::temp_dir().join(format!("{}.journal", Uuid::new_v4()));
+        let entrypoint_id = Uuid::new_v4();
+        let first = response(entrypoint_id, "<First/>");
+        let second = response(entrypoint_id, "<Second/>");
+        let third = response(entrypoint_id, "<Third/>");
+
+        let (journal, pending) = Journal::open(&path).unwrap();
+        assert!(pending.is_empty());
+
+        journal.push(first.clone()).unwrap();
+        journal.push(second.clone()).unwrap();
+        journal.push(third.clone()).unwrap();
+        journal
+            .confirm(entrypoint_id, "master", first.request_id)
+            .unwrap();
+        journal
+            .dead_letter(entrypoint_id, "master", third.request_id)
+            .unwrap();
+        journal.sync().await.unwrap();
+        drop(journal);
+
+        // a record cut in the middle
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"op\":\"push\",\"entry").unwrap();
+
+        let (journal, pending) = Journal::open(&path).unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].request_id, second.request_id);
+        assert_eq!(pending[0].response.body.xml.decode().unwrap(), "<Second/>");
+        assert_eq!(pending[0].response.message_id, second.response.message_id);
+        assert!(!pending[0].dead_letter);
+        assert!(pending[1].dead_letter);
+
+        // the compacted journal keeps the dead letter until it is requeued
+        journal
+            .requeue(entrypoint_id, "master", third.request_id)
+            .unwrap();
+        journal.sync().await.unwrap();
+        drop(journal);
+        let (_, pending) = Journal::open(&path).unwrap();
+        assert!(!pending[1].dead_letter);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_compact() {
+        let path = std::env::temp_dir().join(format!("{}.journal", Uuid::new_v4()));
+        let entrypoint_id = Uuid::new_v4();
+        let pending = response(entrypoint_id, "<Pending/>");
+
+        let (journal, _) = Journal::open(&path).unwrap();
+        journal.push(pending.clone()).unwrap();
+        for _ in 0..super::COMPACT_AFTER {
+            let confirmed = response(entrypoint_id, "<Confirmed/>");
+            journal.push(confirmed.clone()).unwrap();
+            journal
+                .confirm(entrypoint_id, "master", confirmed.request_id)
+                .unwrap();
+        }
+        journal.sync().await.unwrap();
+
+        // the records written after the compaction are appended to the new file
+        let last = response(entrypoint_id, "<Last/>");
+        journal.push(last.clone()).unwrap();
+        journal.sync().await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+
+        drop(journal);
+        let (_, restored) = Journal::open(&path).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[0].request_id, pending.request_id);
+        assert_eq!(restored[1].request_id, last.request_id);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
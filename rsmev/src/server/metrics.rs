@@ -0,0 +1,246 @@
+//! Prometheus metrics of the adapter, requires the `metrics` feature
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use uuid::Uuid;
+
+use super::serve::Rsmev;
+use crate::confirm_queue::QueueStats;
+use crate::service::Service;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EntrypointLabels {
+    entrypoint_id: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct NodeLabels {
+    entrypoint_id: String,
+    node_id: String,
+}
+
+impl NodeLabels {
+    fn new(entrypoint_id: Uuid, node_id: &str) -> Self {
+        Self {
+            entrypoint_id: entrypoint_id.to_string(),
+            node_id: node_id.to_string(),
+        }
+    }
+}
+
+pub(crate) struct Metrics {
+    registry: Registry,
+    requests: Family<EntrypointLabels, Counter>,
+    handle_duration: Family<EntrypointLabels, Histogram, fn() -> Histogram>,
+    handle_errors: Family<EntrypointLabels, Counter>,
+    queue_depth: Family<NodeLabels, Gauge>,
+    in_flight: Family<NodeLabels, Gauge>,
+    redeliveries: Family<NodeLabels, Counter>,
+    dead_letters: Family<NodeLabels, Gauge>,
+    confirmations: Family<NodeLabels, Counter>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("rsmev");
+
+        let requests = Family::<EntrypointLabels, Counter>::default();
+        registry.register(
+            "requests",
+            "Requests accepted by the entrypoint",
+            requests.clone(),
+        );
+
+        // 1ms .. ~33s
+        let handle_duration: Family<_, _, fn() -> Histogram> =
+            Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.001, 2.0, 16)));
+        registry.register(
+            "handle_duration_seconds",
+            "Time the service spent handling a request",
+            handle_duration.clone(),
+        );
+
+        let handle_errors = Family::<EntrypointLabels, Counter>::default();
+        registry.register(
+            "handle_errors",
+            "Requests the service failed to handle",
+            handle_errors.clone(),
+        );
+
+        let queue_depth = Family::<NodeLabels, Gauge>::default();
+        registry.register(
+            "queue_depth",
+            "Responses waiting for a confirmation, taken ones included",
+            queue_depth.clone(),
+        );
+
+        let in_flight = Family::<NodeLabels, Gauge>::default();
+        registry.register(
+            "queue_in_flight",
+            "Responses taken by the node and not confirmed yet",
+            in_flight.clone(),
+        );
+
+        let redeliveries = Family::<NodeLabels, Counter>::default();
+        registry.register(
+            "redeliveries",
+            "Responses delivered again after the confirmation TTL expired",
+            redeliveries.clone(),
+        );
+
+        let dead_letters = Family::<NodeLabels, Gauge>::default();
+        registry.register(
+            "dead_letters",
+            "Responses moved to the dead-letter queue after too many deliveries",
+            dead_letters.clone(),
+        );
+
+        let confirmations = Family::<NodeLabels, Counter>::default();
+        registry.register(
+            "confirmations",
+            "Responses confirmed by the node",
+            confirmations.clone(),
+        );
+
+        Self {
+            registry,
+            requests,
+            handle_duration,
+            handle_errors,
+            queue_depth,
+            in_flight,
+            redeliveries,
+            dead_letters,
+            confirmations,
+        }
+    }
+
+    pub fn request(&self, entrypoint_id: Uuid) {
+        self.requests
+            .get_or_create(&EntrypointLabels {
+                entrypoint_id: entrypoint_id.to_string(),
+            })
+            .inc();
+    }
+
+    pub fn handled(&self, entrypoint_id: Uuid, elapsed: Duration, failed: bool) {
+        let labels = EntrypointLabels {
+            entrypoint_id: entrypoint_id.to_string(),
+        };
+
+        self.handle_duration
+            .get_or_create(&labels)
+            .observe(elapsed.as_secs_f64());
+        if failed {
+            self.handle_errors.get_or_create(&labels).inc();
+        }
+    }
+
+    pub fn redelivered(&self, entrypoint_id: Uuid, node_id: &str, count: u64) {
+        if count > 0 {
+            self.redeliveries
+                .get_or_create(&NodeLabels::new(entrypoint_id, node_id))
+                .inc_by(count);
+        }
+    }
+
+    pub fn confirmed(&self, entrypoint_id: Uuid, node_id: &str) {
+        self.confirmations
+            .get_or_create(&NodeLabels::new(entrypoint_id, node_id))
+            .inc();
+    }
+
+    fn queue(&self, entrypoint_id: Uuid, node_id: &str, stats: QueueStats) {
+        let labels = NodeLabels::new(entrypoint_id, node_id);
+
+        self.queue_depth
+            .get_or_create(&labels)
+            .set(stats.len as i64);
+        self.in_flight
+            .get_or_create(&labels)
+            .set(stats.in_flight as i64);
+        self.dead_letters
+            .get_or_create(&labels)
+            .set(stats.dead_letters as i64);
+    }
+}
+
+/// Scrape handler, the queue gauges are updated on every scrape
+pub(crate) async fn export<S: Service>(State(state): State<Arc<Rsmev<S>>>) -> Response {
+    let metrics = &state.metrics;
+    for client in state.clients.iter() {
+        for (node_id, stats) in client.node_stats() {
+            metrics.queue(*client.key(), &node_id, stats);
+        }
+    }
+
+    let mut body = String::new();
+    if let Err(e) = encode(&mut body, &metrics.registry) {
+        tracing::error!(error = %e, "failed to encode metrics");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::confirm_queue::QueueStats;
+
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    pub fn test_encode() {
+        let metrics = Metrics::new();
+        let entrypoint_id = Uuid::new_v4();
+
+        metrics.request(entrypoint_id);
+        metrics.handled(entrypoint_id, Duration::from_millis(3), true);
+        metrics.redelivered(entrypoint_id, "master", 2);
+        metrics.queue(
+            entrypoint_id,
+            "master",
+            QueueStats {
+                len: 3,
+                in_flight: 1,
+                oldest_age: None,
+                redeliveries: 2,
+                dead_letters: 1,
+            },
+        );
+
+        let mut body = String::new();
+        prometheus_client::encoding::text::encode(&mut body, &metrics.registry).unwrap();
+
+        let node = format!("{{entrypoint_id=\"{entrypoint_id}\",node_id=\"master\"}}");
+        assert!(body.contains(&format!(
+            "rsmev_requests_total{{entrypoint_id=\"{entrypoint_id}\"}} 1"
+        )));
+        assert!(body.contains(&format!(
+            "rsmev_handle_errors_total{{entrypoint_id=\"{entrypoint_id}\"}} 1"
+        )));
+        assert!(body.contains(&format!("rsmev_redeliveries_total{node} 2")));
+        assert!(body.contains(&format!("rsmev_queue_depth{node} 3")));
+        assert!(body.contains(&format!("rsmev_queue_in_flight{node} 1")));
+        assert!(body.contains(&format!("rsmev_dead_letters{node} 1")));
+    }
+}
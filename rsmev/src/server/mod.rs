@@ -1,8 +1,34 @@
+mod admin;
+pub mod api;
+mod auth;
 pub mod body;
+mod builder;
 pub(crate) mod client;
+mod config;
+mod entrypoint;
+mod error;
 pub(crate) mod extractor;
+pub mod fault;
 mod serve;
+mod status;
+#[cfg(feature = "tls")]
+mod tls;
 
 mod handler_service;
+mod journal;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod worker;
 
-pub use serve::serve;
+pub use auth::{read_api_keys, ApiKey};
+pub use builder::RsmevServerBuilder;
+pub use config::{
+    ServeConfig, DEFAULT_CHANNEL_BUFFER_SIZE, DEFAULT_CONCURRENCY, DEFAULT_NODE_ID,
+    DEFAULT_NODE_ID_HEADER, DEFAULT_PATH_PREFIX, DEFAULT_QUEUE_TTL,
+};
+pub use entrypoint::{read_entrypoints, Entrypoint};
+pub use serve::{
+    serve, serve_with_config, serve_with_file_store, serve_with_shutdown, RsmevServer,
+};
+#[cfg(feature = "tls")]
+pub use tls::{ClientCertificate, TlsConfig};
@@ -1,32 +1,323 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    future::{Future, IntoFuture},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use super::{body::Body, client::Client, extractor::HeaderNodeId, handler_service::HandlerService};
+use super::builder::RsmevServerBuilder;
+use super::config::ServeConfig;
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+#[cfg(feature = "tls")]
+use super::tls::TlsConfig;
+use super::{
+    admin,
+    api::{ConfirmResponse, GetResponse, SendRequest, SendResponse, StatusResponse},
+    auth::{self, Auth},
+    body::Body,
+    client::{Client, Delivery, SendError},
+    entrypoint::{Entrypoint, Registry},
+    error::ApiError,
+    extractor::{HeaderNodeId, NodeIdHeader},
+    handler_service::HandlerService,
+    journal::{Journal, PendingResponse},
+    worker::WorkerConfig,
+};
+use crate::confirm_queue::Confirmation;
+use crate::file_store::FileStore;
 use crate::service::Service;
 
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
-    routing::post,
-    Json, Router,
+    http::{HeaderName, StatusCode},
+    routing::{get, post},
+    Extension, Json, Router,
 };
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
 pub use tokio::net::TcpListener;
 use uuid::Uuid;
 
+/// Serve any entrypoint, it is created on the first request
 pub async fn serve<S: Service>(listener: TcpListener, service: S) -> Result<(), std::io::Error> {
-    let state = Arc::new(Rsmev::new(service));
+    RsmevServer::builder()
+        .auto_create_entrypoints(true)
+        .build(service)?
+        .serve(listener)
+        .await
+}
+
+/// Serve any entrypoint with the files of `files`
+pub async fn serve_with_file_store<S: Service, F: FileStore>(
+    listener: TcpListener,
+    service: S,
+    files: F,
+) -> Result<(), std::io::Error> {
+    RsmevServer::builder()
+        .auto_create_entrypoints(true)
+        .file_store(files)
+        .build(service)?
+        .serve(listener)
+        .await
+}
 
-    let rsmev_routes = Router::new()
-        .route("/sendrequest", post(send_request))
-        .route("/getresponse", post(get_response))
-        .route("/confirmprocessing/:request_id", post(confirm_request))
-        .with_state(state);
+pub async fn serve_with_config<S: Service>(
+    listener: TcpListener,
+    service: S,
+    config: ServeConfig,
+) -> Result<(), std::io::Error> {
+    serve_with_shutdown(listener, service, config, std::future::pending()).await
+}
 
-    let routes = Router::new().nest("/api/smev/:entrypoint_id", rsmev_routes);
-    #[cfg(feature = "tracing_requests")]
-    let routes = routes.layer(axum::middleware::from_fn(middleware::print_request_body));
+/// Serve until `signal` resolves, then reject new requests, wait for the
+/// open connections and the queued requests and flush the journal
+pub async fn serve_with_shutdown<S: Service>(
+    listener: TcpListener,
+    service: S,
+    config: ServeConfig,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), std::io::Error> {
+    RsmevServer::builder()
+        .config(config)
+        .build(service)?
+        .serve_with_shutdown(listener, signal)
+        .await
+}
 
-    axum::serve(listener, routes).await
+/// SMEV adapter mock built by [`RsmevServerBuilder`]
+///
+/// It either serves the routes itself, or gives them away as a [`Router`]
+/// to be nested into another application, then [`RsmevServer::shutdown`]
+/// should be awaited on exit
+pub struct RsmevServer<S: Service> {
+    state: Arc<Rsmev<S>>,
+    router: Router,
+    admin_router: Router,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    #[cfg(feature = "tls")]
+    node_id_header: HeaderName,
+}
+
+impl<S: Service> RsmevServer<S> {
+    pub fn builder() -> RsmevServerBuilder<S> {
+        RsmevServerBuilder::new()
+    }
+
+    pub(crate) fn new(
+        service: S,
+        services: Vec<(String, S)>,
+        config: ServeConfig,
+    ) -> Result<Self, std::io::Error> {
+        let node_id_header = HeaderName::try_from(config.node_id_header.as_str())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+        #[cfg(feature = "crypto")]
+        let crypto = Arc::new(config.crypto);
+        let handler = |service: S| -> Result<Arc<HandlerService<S>>, std::io::Error> {
+            #[cfg(feature = "xsd")]
+            let schemas =
+                crate::xsd::SchemaSet::load(&service.schemas()).map_err(std::io::Error::other)?;
+            #[cfg(not(feature = "xsd"))]
+            if !service.schemas().is_empty() {
+                tracing::warn!("service declares xsd schemas, but the `xsd` feature is disabled");
+            }
+
+            let handler = HandlerService::new(service, config.file_store.clone());
+            #[cfg(feature = "crypto")]
+            let handler = handler.with_crypto(crypto.clone());
+            #[cfg(feature = "xsd")]
+            let handler = handler.with_schemas(schemas);
+
+            Ok(Arc::new(handler))
+        };
+
+        let default_service = handler(service)?;
+        let services = services
+            .into_iter()
+            .map(|(name, service)| Ok((name, handler(service)?)))
+            .collect::<Result<HashMap<_, _>, std::io::Error>>()?;
+
+        let registry = Registry::new(config.entrypoints, config.auto_create_entrypoints);
+        for entrypoint in registry.iter() {
+            match &entrypoint.service {
+                Some(name) if !services.contains_key(name) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "entrypoint `{}` uses unknown service `{name}`",
+                            entrypoint.name
+                        ),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        let (journal, pending) = match &config.journal {
+            Some(path) => {
+                let (journal, pending) = Journal::open(path)?;
+                (Some(Arc::new(journal)), pending)
+            }
+            None => (None, Vec::new()),
+        };
+
+        let state = Arc::new(Rsmev {
+            service: default_service,
+            services,
+            clients: DashMap::new(),
+            journal,
+            queue_ttl: config.queue_ttl,
+            max_deliveries: config.max_deliveries,
+            workers: WorkerConfig {
+                concurrency: config.concurrency,
+                node_concurrency: config.node_concurrency,
+                preserve_node_order: config.preserve_node_order,
+                buffer: config.channel_buffer_size,
+            },
+            send_timeout: config.send_timeout,
+            default_node_id: config.default_node_id,
+            registry,
+            #[cfg(feature = "metrics")]
+            metrics: Arc::new(Metrics::new()),
+            closing: AtomicBool::new(false),
+        });
+        state.restore(pending);
+
+        let rsmev_routes = Router::new()
+            .route("/sendrequest", post(send_request))
+            .route("/getresponse", post(get_response))
+            .route("/confirmprocessing/:request_id", post(confirm_request))
+            .route("/status/:request_id", get(request_status))
+            .with_state(state.clone());
+        let admin_router = admin::routes(state.clone());
+        let (rsmev_routes, admin_router) = if config.api_keys.is_empty() {
+            (rsmev_routes, admin_router)
+        } else {
+            let auth = Arc::new(Auth::new(config.api_keys));
+            (
+                rsmev_routes.route_layer(axum::middleware::from_fn_with_state(
+                    auth.clone(),
+                    auth::authenticate,
+                )),
+                admin_router.route_layer(axum::middleware::from_fn_with_state(
+                    auth,
+                    auth::authenticate_admin,
+                )),
+            )
+        };
+
+        let prefix = config.path_prefix.trim_end_matches('/');
+        let router = Router::new()
+            .nest(&format!("{prefix}/:entrypoint_id"), rsmev_routes)
+            .layer(Extension(NodeIdHeader(node_id_header.clone())));
+        // probes and scrapes go without the api key
+        let router = router
+            .route("/health", get(health))
+            .route("/ready", get(ready::<S>).with_state(state.clone()));
+        #[cfg(feature = "metrics")]
+        let router = router.route(
+            "/metrics",
+            get(super::metrics::export).with_state(state.clone()),
+        );
+        let router = match &config.admin_path_prefix {
+            Some(prefix) => router.nest(prefix.trim_end_matches('/'), admin_router.clone()),
+            None => router,
+        };
+        #[cfg(feature = "tracing_requests")]
+        let router = router.layer(axum::middleware::from_fn(middleware::print_request_body));
+
+        Ok(Self {
+            state,
+            router,
+            admin_router,
+            #[cfg(feature = "tls")]
+            tls: config.tls,
+            #[cfg(feature = "tls")]
+            node_id_header,
+        })
+    }
+
+    /// Routes of the adapter, to be nested or merged into another router
+    pub fn router(&self) -> Router {
+        self.router.clone()
+    }
+
+    /// Routes of the admin api without a prefix, to be served separately
+    pub fn admin_router(&self) -> Router {
+        self.admin_router.clone()
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> Result<(), std::io::Error> {
+        self.serve_with_shutdown(listener, std::future::pending())
+            .await
+    }
+
+    /// Serve until `signal` resolves, then reject new requests, wait for the
+    /// open connections and the queued requests and flush the journal
+    pub async fn serve_with_shutdown(
+        self,
+        listener: TcpListener,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), std::io::Error> {
+        let closing = self.state.clone();
+        let (closed_tx, closed_rx) = tokio::sync::oneshot::channel();
+        let signal = async move {
+            signal.await;
+            tracing::info!("shutting down");
+            closing.close();
+            let _ = closed_tx.send(());
+        };
+
+        #[cfg(feature = "tls")]
+        let result = match self.tls {
+            Some(tls) => {
+                tracing::info!("serving https");
+                super::tls::serve(listener, self.router, tls, self.node_id_header, signal).await
+            }
+            None => serve_plain(listener, self.router, signal, closed_rx).await,
+        };
+        #[cfg(not(feature = "tls"))]
+        let result = serve_plain(listener, self.router, signal, closed_rx).await;
+
+        self.state.drain().await;
+        result
+    }
+
+    /// Reject new requests, handle the queued ones and flush the journal
+    pub async fn shutdown(&self) {
+        self.state.drain().await;
+    }
+}
+
+/// Serve http until `signal` resolves, then wait for the open connections
+/// at most `CLOSE_TIMEOUT` after `closed`, axum never closes a connection
+/// that has not sent its first request
+async fn serve_plain(
+    listener: TcpListener,
+    router: Router,
+    signal: impl Future<Output = ()> + Send + 'static,
+    closed: tokio::sync::oneshot::Receiver<()>,
+) -> Result<(), std::io::Error> {
+    let serve = axum::serve(listener, router).with_graceful_shutdown(signal);
+    let mut serve = pin!(serve.into_future());
+
+    tokio::select! {
+        result = serve.as_mut() => return result,
+        Ok(()) = closed => {}
+    }
+
+    match tokio::time::timeout(CLOSE_TIMEOUT, serve).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!("stopped waiting for the open connections");
+            Ok(())
+        }
+    }
 }
 
 #[cfg(feature = "tracing_requests")]
@@ -73,6 +364,7 @@ mod middleware {
     }
 
     // extractor that shows how to consume the request body upfront
+    #[allow(dead_code)]
     struct BufferRequestBody(Bytes);
 
     // we must implement `FromRequest` (and not `FromRequestParts`) to consume the body
@@ -95,59 +387,79 @@ mod middleware {
     }
 }
 
-#[derive(serde::Deserialize, Debug)]
-struct SendRequest {
-    #[serde(flatten)]
-    body: Body,
-}
+type RsmevState<S> = State<Arc<Rsmev<S>>>;
+
+/// Time the open connections may take to finish after the shutdown signal
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time the readiness check of the service may take
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(serde::Serialize)]
-#[serde(rename_all = "camelCase")]
-struct SendResponse {
-    request_id: Uuid,
+struct Status {
+    status: &'static str,
+}
+
+async fn health() -> Json<Status> {
+    Json(Status { status: "ok" })
+}
+
+async fn ready<S: Service>(State(state): RsmevState<S>) -> Result<Json<Status>, ApiError> {
+    if state.closing.load(Ordering::Relaxed) {
+        return Err(ApiError::ShuttingDown);
+    }
+
+    // every service, an entrypoint may be bound to any of them
+    let ready = async {
+        for service in std::iter::once(&state.service).chain(state.services.values()) {
+            service.ready().await?;
+        }
+        Ok::<_, S::Error>(())
+    };
+
+    match tokio::time::timeout(READY_TIMEOUT, ready).await {
+        Ok(Ok(())) => Ok(Json(Status { status: "ready" })),
+        Ok(Err(e)) => Err(ApiError::NotReady(e.to_string())),
+        Err(_) => Err(ApiError::NotReady("readiness check timed out".to_string())),
+    }
 }
 
-type RsmevState<S> = State<Arc<Rsmev<S>>>;
 async fn send_request<S: Service>(
     State(state): RsmevState<S>,
     Path(entrypoint_id): Path<Uuid>,
     HeaderNodeId(node_id): HeaderNodeId,
     Json(request): Json<SendRequest>,
-) -> Json<SendResponse> {
-    let task_id = state.push_task(entrypoint_id, node_id, request.body).await;
+) -> Result<Json<SendResponse>, ApiError> {
+    let task_id = state
+        .push_task(entrypoint_id, node_id, request.body)
+        .await?;
 
-    Json(SendResponse {
+    Ok(Json(SendResponse {
         request_id: task_id,
-    })
-}
-
-#[derive(serde::Serialize)]
-#[serde(rename_all = "camelCase")]
-struct GetResponse {
-    rec_id: Uuid,
-    request_id: Uuid,
-    message_id: Uuid,
-    #[serde(flatten)]
-    body: Body,
+    }))
 }
 
 async fn get_response<S: Service>(
     State(state): RsmevState<S>,
     Path(entrypoint_id): Path<Uuid>,
     HeaderNodeId(node_id): HeaderNodeId,
-) -> (StatusCode, Json<Option<GetResponse>>) {
-    if let Some((request_id, body)) = state.pop_task(entrypoint_id, node_id).await {
-        (
+) -> Result<(StatusCode, Json<Option<GetResponse>>), ApiError> {
+    if let Some(delivery) = state.pop_task(entrypoint_id, node_id)? {
+        let response = delivery.response;
+        Ok((
             StatusCode::OK,
             Json(Some(GetResponse {
-                rec_id: request_id,
-                request_id,
-                message_id: Uuid::new_v4(),
-                body,
+                rec_id: response.rec_id,
+                request_id: delivery.request_id,
+                message_id: response.message_id,
+                delivery_attempt: Some(delivery.attempt),
+                queued_at_ms: Some(response.queued_at_ms),
+                first_delivered_at_ms: Some(delivery.first_delivered_at_ms),
+                body: response.body,
             })),
-        )
+        ))
     } else {
-        (StatusCode::NOT_FOUND, Json(None))
+        Ok((StatusCode::NOT_FOUND, Json(None)))
     }
 }
 
@@ -155,20 +467,86 @@ async fn confirm_request<S: Service>(
     State(state): RsmevState<S>,
     Path((entrypoint_id, request_id)): Path<(Uuid, Uuid)>,
     HeaderNodeId(node_id): HeaderNodeId,
-) {
-    state.confirm_task(entrypoint_id, node_id, request_id).await;
+) -> Result<Json<ConfirmResponse>, ApiError> {
+    match state.confirm_task(entrypoint_id, node_id, request_id)? {
+        // a repeated confirmation, e.g. a retry after a lost reply, is not an error
+        Confirmation::Confirmed | Confirmation::AlreadyConfirmed => {
+            Ok(Json(ConfirmResponse { request_id }))
+        }
+        Confirmation::DeadLetter => Err(ApiError::DeadLetter(request_id)),
+        Confirmation::Unknown => Err(ApiError::UnknownRequest(request_id)),
+    }
+}
+
+/// Status of a request of any node of the entrypoint
+async fn request_status<S: Service>(
+    State(state): RsmevState<S>,
+    Path((entrypoint_id, request_id)): Path<(Uuid, Uuid)>,
+    HeaderNodeId(node_id): HeaderNodeId,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state
+        .request_status(entrypoint_id, node_id, request_id)
+        .map(Json)
 }
 
-struct Rsmev<S: Service> {
+pub(crate) struct Rsmev<S: Service> {
     service: Arc<HandlerService<S>>,
-    clients: DashMap<Uuid, Client>,
+    /// Services the entrypoints are bound to by name
+    services: HashMap<String, Arc<HandlerService<S>>>,
+    pub(super) clients: DashMap<Uuid, Client<S>>,
+    journal: Option<Arc<Journal>>,
+    queue_ttl: Duration,
+    max_deliveries: Option<u32>,
+    workers: WorkerConfig,
+    send_timeout: Option<Duration>,
+    default_node_id: String,
+    pub(super) registry: Registry,
+    #[cfg(feature = "metrics")]
+    pub(super) metrics: Arc<Metrics>,
+    closing: AtomicBool,
 }
 
 impl<S: Service> Rsmev<S> {
-    pub fn new(service: S) -> Self {
-        Self {
-            service: Arc::new(HandlerService::new(service)),
-            clients: DashMap::new(),
+    /// Reject new requests
+    fn close(&self) {
+        self.closing.store(true, Ordering::Relaxed);
+    }
+
+    /// Handle the queued requests of every client and flush the journal
+    async fn drain(&self) {
+        self.close();
+
+        let entrypoints: Vec<Uuid> = self.clients.iter().map(|c| *c.key()).collect();
+        for entrypoint_id in entrypoints {
+            if let Some((_, client)) = self.clients.remove(&entrypoint_id) {
+                client.shutdown().await;
+            }
+        }
+
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.sync().await {
+                tracing::error!(error = %e, "failed to flush journal");
+            }
+        }
+    }
+
+    fn restore(&self, pending: Vec<PendingResponse>) {
+        if !pending.is_empty() {
+            tracing::info!(count = pending.len(), "restoring not confirmed responses");
+        }
+
+        for response in pending {
+            match self.get_client(response.entrypoint_id, &response.node_id) {
+                Ok(client) => client.restore(
+                    response.node_id,
+                    response.request_id,
+                    response.response,
+                    response.dead_letter,
+                ),
+                Err(e) => {
+                    tracing::warn!(request_id = %response.request_id, error = %e, "dropped journaled response")
+                }
+            }
         }
     }
 
@@ -177,37 +555,651 @@ impl<S: Service> Rsmev<S> {
         entrypoint_id: Uuid,
         node_id: Option<String>,
         body: Body,
-    ) -> Uuid {
-        self.get_client(entrypoint_id)
-            .push_task(node_id, body)
+    ) -> Result<Uuid, ApiError> {
+        if self.closing.load(Ordering::Relaxed) {
+            return Err(ApiError::ShuttingDown);
+        }
+
+        // the request is decoded before the client entry is locked
+        let node_id = self.node_id(node_id);
+        let entrypoint = self.registry.get(entrypoint_id, &node_id)?;
+        // files, signatures and schemas are checked off the async workers
+        let service = self.service_of(entrypoint).clone();
+        let message = match tokio::task::spawn_blocking(move || service.to_message(body)).await {
+            Ok(message) => message?,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => return Err(ApiError::ShuttingDown),
+        };
+
+        // the map entry is not locked while waiting for room in the buffer
+        let sender = self.get_client(entrypoint_id, &node_id)?.sender();
+        let request_id = sender
+            .send(node_id, message, self.send_timeout)
             .await
+            .map_err(|e| match e {
+                SendError::Full => ApiError::QueueFull(entrypoint_id),
+                SendError::Closed => ApiError::EntrypointUnavailable(entrypoint_id),
+            })?;
+        // only the requests handed over to the worker are counted
+        #[cfg(feature = "metrics")]
+        self.metrics.request(entrypoint_id);
+
+        Ok(request_id)
     }
 
-    pub async fn pop_task(
+    pub fn pop_task(
         &self,
         entrypoint_id: Uuid,
         node_id: Option<String>,
-    ) -> Option<(Uuid, Body)> {
-        self.get_client(entrypoint_id).pop_task(node_id).await
+    ) -> Result<Option<Delivery>, ApiError> {
+        let node_id = self.node_id(node_id);
+        let client = self.get_client(entrypoint_id, &node_id)?;
+
+        Ok(client.pop_task(node_id))
     }
 
-    pub async fn confirm_task(
+    pub fn confirm_task(
         &self,
         entrypoint_id: Uuid,
         node_id: Option<String>,
         request_id: Uuid,
-    ) {
-        self.get_client(entrypoint_id)
-            .confirm_task(node_id, &request_id)
-            .await;
+    ) -> Result<Confirmation, ApiError> {
+        let node_id = self.node_id(node_id);
+        let client = self.get_client(entrypoint_id, &node_id)?;
+
+        Ok(client.confirm_task(node_id, &request_id))
     }
 
-    pub fn get_client(
+    pub fn request_status(
         &self,
         entrypoint_id: Uuid,
-    ) -> dashmap::mapref::one::RefMut<'_, Uuid, Client> {
+        node_id: Option<String>,
+        request_id: Uuid,
+    ) -> Result<StatusResponse, ApiError> {
+        let node_id = self.node_id(node_id);
+        self.registry.get(entrypoint_id, &node_id)?;
+
+        // a client is opened by the first request only
         self.clients
-            .entry(entrypoint_id)
-            .or_insert(Client::new(self.service.clone()))
+            .get(&entrypoint_id)
+            .and_then(|client| client.status(&request_id))
+            .ok_or(ApiError::UnknownRequest(request_id))
+    }
+
+    /// Service bound to the entrypoint, the default one when it is not bound
+    fn service_of(&self, entrypoint: Option<&Entrypoint>) -> &Arc<HandlerService<S>> {
+        entrypoint
+            .and_then(|e| e.service.as_ref())
+            .and_then(|name| self.services.get(name))
+            .unwrap_or(&self.service)
+    }
+
+    /// Requests without the node id header belong to the default node
+    fn node_id(&self, node_id: Option<String>) -> String {
+        node_id.unwrap_or_else(|| self.default_node_id.clone())
+    }
+
+    /// Client of the registered entrypoint, unknown ones are created only
+    /// in the auto-create mode. No client is opened once the server is
+    /// closing, `drain` would miss its worker
+    pub fn get_client(
+        &self,
+        entrypoint_id: Uuid,
+        node_id: &str,
+    ) -> Result<dashmap::mapref::one::RefMut<'_, Uuid, Client<S>>, ApiError> {
+        let entrypoint = self.registry.get(entrypoint_id, node_id)?;
+
+        let entry = match self.clients.entry(entrypoint_id) {
+            Entry::Occupied(entry) => return Ok(entry.into_ref()),
+            Entry::Vacant(entry) => entry,
+        };
+        // checked under the lock of the entry, `drain` takes it after `close`
+        if self.closing.load(Ordering::Relaxed) {
+            return Err(ApiError::ShuttingDown);
+        }
+
+        tracing::info!(
+            %entrypoint_id,
+            name = entrypoint.map(|e| e.name.as_str()),
+            "entrypoint opened"
+        );
+        let queue_ttl = entrypoint
+            .and_then(|e| e.queue_ttl)
+            .unwrap_or(self.queue_ttl);
+        let workers = WorkerConfig {
+            concurrency: entrypoint
+                .and_then(|e| e.concurrency)
+                .unwrap_or(self.workers.concurrency),
+            ..self.workers
+        };
+
+        let client = Client::new(
+            entrypoint_id,
+            self.service_of(entrypoint).clone(),
+            self.journal.clone(),
+            queue_ttl,
+            self.max_deliveries,
+            workers,
+            #[cfg(feature = "metrics")]
+            self.metrics.clone(),
+        );
+        Ok(entry.insert(client))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{RsmevServer, TcpListener};
+    use crate::api::RequestStatus;
+    use crate::server::body::EncodedXml;
+    use crate::server::entrypoint::Entrypoint;
+    use crate::server::journal::Journal;
+    use crate::service::{Message, Service};
+
+    use std::time::Duration;
+
+    use axum::{
+        body::Body as HttpBody,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    pub struct Echo {
+        #[serde(rename = "Text")]
+        pub text: String,
+    }
+
+    /// Result of the readiness probe of `EchoService`
+    #[derive(Clone, Copy, Default)]
+    pub enum Probe {
+        #[default]
+        Ready,
+        Fail,
+        Hang,
+    }
+
+    /// Echoes the text with the prefix, `fail` fails and `sleep:<ms>` waits first
+    #[derive(Clone, Copy, Default)]
+    pub struct EchoService {
+        pub prefix: &'static str,
+        pub probe: Probe,
+    }
+
+    impl Service for EchoService {
+        type Request = Echo;
+        type Response = Echo;
+        type Error = std::io::Error;
+
+        async fn handle(&self, content: Message<Echo>) -> Result<Message<Echo>, Self::Error> {
+            let text = content.content.text;
+            if text == "fail" {
+                return Err(std::io::Error::other("service failed"));
+            }
+            if let Some(ms) = text.strip_prefix("sleep:") {
+                tokio::time::sleep(Duration::from_millis(ms.parse().unwrap())).await;
+            }
+
+            Ok(Message {
+                content: Echo {
+                    text: format!("{}{text}", self.prefix),
+                },
+                files: Vec::new(),
+            })
+        }
+
+        async fn ready(&self) -> Result<(), Self::Error> {
+            match self.probe {
+                Probe::Ready => Ok(()),
+                Probe::Fail => Err(std::io::Error::other("database is down")),
+                Probe::Hang => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Call the router, the body is `Null` when it is not json
+    pub async fn call(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = match body {
+            Some(body) => request
+                .header("content-type", "application/json")
+                .body(HttpBody::from(body.to_string())),
+            None => request.body(HttpBody::empty()),
+        }
+        .unwrap();
+
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    pub async fn send(router: &Router, entrypoint_id: Uuid, text: &str) -> (StatusCode, Value) {
+        let xml = EncodedXml::encode(&format!("<Echo><Text>{text}</Text></Echo>"));
+        let uri = format!("/api/smev/{entrypoint_id}/sendrequest");
+
+        call(router, "POST", &uri, &[], Some(json!({ "xml": xml }))).await
+    }
+
+    /// Request id of the accepted request
+    pub async fn send_ok(router: &Router, entrypoint_id: Uuid, text: &str) -> Uuid {
+        let (status, body) = send(router, entrypoint_id, text).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        serde_json::from_value(body["requestId"].clone()).unwrap()
+    }
+
+    pub async fn pop(router: &Router, entrypoint_id: Uuid) -> (StatusCode, Value) {
+        let uri = format!("/api/smev/{entrypoint_id}/getresponse");
+        call(router, "POST", &uri, &[], None).await
+    }
+
+    pub async fn confirm(
+        router: &Router,
+        entrypoint_id: Uuid,
+        request_id: Uuid,
+    ) -> (StatusCode, Value) {
+        let uri = format!("/api/smev/{entrypoint_id}/confirmprocessing/{request_id}");
+        call(router, "POST", &uri, &[], None).await
+    }
+
+    pub async fn request_status(
+        router: &Router,
+        entrypoint_id: Uuid,
+        request_id: Uuid,
+    ) -> (StatusCode, Value) {
+        let uri = format!("/api/smev/{entrypoint_id}/status/{request_id}");
+        call(router, "GET", &uri, &[], None).await
+    }
+
+    /// Poll the status of the request until it reaches `expected`
+    pub async fn wait_status(
+        router: &Router,
+        entrypoint_id: Uuid,
+        request_id: Uuid,
+        expected: RequestStatus,
+    ) {
+        let expected = serde_json::to_value(expected).unwrap();
+        for _ in 0..500 {
+            let (_, body) = request_status(router, entrypoint_id, request_id).await;
+            if body["status"] == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("request {request_id} did not reach {expected}");
+    }
+
+    /// Decoded text of the echoed response
+    pub fn response_text(response: &Value) -> String {
+        let xml: EncodedXml = serde_json::from_value(response["xml"].clone()).unwrap();
+        xml.deserialize::<Echo>().unwrap().text
+    }
+
+    #[tokio::test]
+    pub async fn test_service_binding() {
+        let echo_id = Uuid::new_v4();
+        let upper_id = Uuid::new_v4();
+        let upper = EchoService {
+            prefix: "upper:",
+            ..Default::default()
+        };
+        let server = RsmevServer::builder()
+            .entrypoint(Entrypoint::new(echo_id, "echo"))
+            .entrypoint(Entrypoint::new(upper_id, "upper").with_service("upper"))
+            .service("upper", upper)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        for (entrypoint_id, expected) in [(echo_id, "hi"), (upper_id, "upper:hi")] {
+            let request_id = send_ok(&router, entrypoint_id, "hi").await;
+            wait_status(
+                &router,
+                entrypoint_id,
+                request_id,
+                RequestStatus::ResponseReady,
+            )
+            .await;
+
+            let (status, response) = pop(&router, entrypoint_id).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(response_text(&response), expected);
+        }
+
+        // an entrypoint bound to an unknown service is rejected on build
+        assert!(RsmevServer::builder()
+            .entrypoint(Entrypoint::new(echo_id, "echo").with_service("missing"))
+            .build(EchoService::default())
+            .is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_channel_buffer_size() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .channel_buffer_size(1)
+            .concurrency(1)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        // one request is handled, one waits, the buffer is full
+        let first = send_ok(&router, entrypoint_id, "sleep:300").await;
+        wait_status(&router, entrypoint_id, first, RequestStatus::Processing).await;
+        send_ok(&router, entrypoint_id, "second").await;
+
+        let (status, body) = send(&router, entrypoint_id, "third").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        assert_eq!(body["code"], "QUEUE_FULL");
+    }
+
+    #[tokio::test]
+    pub async fn test_confirm_dead_letter() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .queue_ttl(Duration::from_millis(50))
+            .max_deliveries(1)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        let request_id = send_ok(&router, entrypoint_id, "poison").await;
+        wait_status(
+            &router,
+            entrypoint_id,
+            request_id,
+            RequestStatus::ResponseReady,
+        )
+        .await;
+        let (status, _) = pop(&router, entrypoint_id).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the expired response is moved to the dead letters by the next pop
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let (status, _) = pop(&router, entrypoint_id).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = confirm(&router, entrypoint_id, request_id).await;
+        assert_eq!(status, StatusCode::GONE, "{body}");
+        assert_eq!(body["code"], "DEAD_LETTER");
+
+        let (status, body) = confirm(&router, entrypoint_id, Uuid::new_v4()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+        assert_eq!(body["code"], "UNKNOWN_REQUEST");
+    }
+
+    #[tokio::test]
+    pub async fn test_redelivery() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .queue_ttl(Duration::from_millis(50))
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        let request_id = send_ok(&router, entrypoint_id, "hi").await;
+        wait_status(
+            &router,
+            entrypoint_id,
+            request_id,
+            RequestStatus::ResponseReady,
+        )
+        .await;
+        let (_, first) = pop(&router, entrypoint_id).await;
+        assert_eq!(first["deliveryAttempt"], 1);
+
+        // not confirmed within the TTL, the same response is delivered again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let (status, second) = pop(&router, entrypoint_id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["requestId"], request_id.to_string());
+        assert_eq!(second["messageId"], first["messageId"]);
+        assert_eq!(second["recId"], first["recId"]);
+        assert_eq!(second["deliveryAttempt"], 2);
+        assert_eq!(second["firstDeliveredAtMs"], first["firstDeliveredAtMs"]);
+    }
+
+    #[tokio::test]
+    pub async fn test_request_status() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .concurrency(1)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        // the second request waits for the only worker
+        let first = send_ok(&router, entrypoint_id, "sleep:200").await;
+        wait_status(&router, entrypoint_id, first, RequestStatus::Processing).await;
+        let second = send_ok(&router, entrypoint_id, "hi").await;
+        let (status, body) = request_status(&router, entrypoint_id, second).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "QUEUED");
+        assert_eq!(body["nodeId"], "master");
+        assert_eq!(body["fault"], false);
+
+        wait_status(&router, entrypoint_id, second, RequestStatus::ResponseReady).await;
+        for _ in [first, second] {
+            let (status, _) = pop(&router, entrypoint_id).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        for request_id in [first, second] {
+            wait_status(&router, entrypoint_id, request_id, RequestStatus::Delivered).await;
+        }
+
+        for request_id in [first, second] {
+            let (status, _) = confirm(&router, entrypoint_id, request_id).await;
+            assert_eq!(status, StatusCode::OK);
+            wait_status(&router, entrypoint_id, request_id, RequestStatus::Confirmed).await;
+        }
+
+        let (status, body) = request_status(&router, entrypoint_id, Uuid::new_v4()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+        assert_eq!(body["code"], "UNKNOWN_REQUEST");
+    }
+
+    #[tokio::test]
+    pub async fn test_service_error() {
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .concurrency(1)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+        let branch = [("node_id", "branch")];
+        let send_uri = format!("/api/smev/{entrypoint_id}/sendrequest");
+        let pop_uri = format!("/api/smev/{entrypoint_id}/getresponse");
+        let send_as_branch = |text: &str| {
+            let xml = EncodedXml::encode(&format!("<Echo><Text>{text}</Text></Echo>"));
+            call(
+                &router,
+                "POST",
+                &send_uri,
+                &branch,
+                Some(json!({ "xml": xml })),
+            )
+        };
+
+        let (_, body) = send_as_branch("fail").await;
+        let failed: Uuid = serde_json::from_value(body["requestId"].clone()).unwrap();
+        let (_, body) = send_as_branch("next").await;
+        let next: Uuid = serde_json::from_value(body["requestId"].clone()).unwrap();
+
+        // the fault is queued for the node of the request only
+        wait_status(&router, entrypoint_id, failed, RequestStatus::Failed).await;
+        let (status, _) = pop(&router, entrypoint_id).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // the worker goes on with the next request
+        wait_status(&router, entrypoint_id, next, RequestStatus::ResponseReady).await;
+        let (_, response) = call(&router, "POST", &pop_uri, &branch, None).await;
+        assert_eq!(response["requestId"], next.to_string());
+        assert_eq!(response_text(&response), "next");
+
+        let (status, fault) = call(&router, "POST", &pop_uri, &branch, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fault["requestId"], failed.to_string());
+        let xml: EncodedXml = serde_json::from_value(fault["xml"].clone()).unwrap();
+        let xml = xml.decode().unwrap();
+        assert!(xml.contains("<Code>SERVICE_ERROR</Code>"), "{xml}");
+        assert!(xml.contains(&failed.to_string()), "{xml}");
+
+        let (_, body) = request_status(&router, entrypoint_id, failed).await;
+        assert_eq!(body["fault"], true);
+    }
+
+    #[tokio::test]
+    pub async fn test_graceful_shutdown() {
+        let path = std::env::temp_dir().join(format!("{}.journal", Uuid::new_v4()));
+        let entrypoint_id = Uuid::new_v4();
+        let server = RsmevServer::builder()
+            .auto_create_entrypoints(true)
+            .concurrency(1)
+            .journal(&path)
+            .build(EchoService::default())
+            .unwrap();
+        let router = server.router();
+
+        let first = send_ok(&router, entrypoint_id, "sleep:200").await;
+        wait_status(&router, entrypoint_id, first, RequestStatus::Processing).await;
+        let second = send_ok(&router, entrypoint_id, "queued").await;
+
+        // new requests are rejected while the queued ones are handled
+        let rejected = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let (status, body) = send(&router, entrypoint_id, "late").await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{body}");
+            assert_eq!(body["code"], "SHUTTING_DOWN");
+        };
+        tokio::join!(server.shutdown(), rejected);
+        drop(server);
+
+        // both responses are flushed to the journal before the shutdown returns
+        let (_, pending) = Journal::open(&path).unwrap();
+        let mut journaled: Vec<Uuid> = pending.iter().map(|p| p.request_id).collect();
+        let mut handled = vec![first, second];
+        journaled.sort();
+        handled.sort();
+        assert_eq!(journaled, handled);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Serve with a connection that never sends a byte and stop the server
+    async fn stop_with_stalled_connection(server: RsmevServer<EchoService>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let serve = tokio::spawn(server.serve_with_shutdown(listener, async move {
+            let _ = stop_rx.await;
+        }));
+
+        let _stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        stop_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(2), serve)
+            .await
+            .expect("shutdown waits for the stalled connection")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_shutdown_stalled_connection() {
+        let server = RsmevServer::builder()
+            .build(EchoService::default())
+            .unwrap();
+        stop_with_stalled_connection(server).await;
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    pub async fn test_shutdown_stalled_handshake() {
+        use crate::crypto::pkcs7::tests::test_identity;
+        use crate::TlsConfig;
+
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = test_identity();
+        std::fs::write(dir.join("cert.pem"), cert.to_pem().unwrap()).unwrap();
+        std::fs::write(dir.join("key.pem"), key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let server = RsmevServer::builder()
+            .tls(TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem")))
+            .build(EchoService::default())
+            .unwrap();
+        stop_with_stalled_connection(server).await;
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn probe(probe: Probe, uri: &str) -> (StatusCode, Value) {
+        // the default service is ready, the probe of every bound service is checked
+        let server = RsmevServer::builder()
+            .service("probed", EchoService { prefix: "", probe })
+            .build(EchoService::default())
+            .unwrap();
+        call(&server.router(), "GET", uri, &[], None).await
+    }
+
+    #[tokio::test]
+    pub async fn test_health() {
+        for probe_result in [Probe::Ready, Probe::Fail] {
+            let (status, body) = probe(probe_result, "/health").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["status"], "ok");
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_ready() {
+        let (status, body) = probe(Probe::Ready, "/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        let (status, body) = probe(Probe::Fail, "/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "NOT_READY");
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains("database is down"));
+
+        let server = RsmevServer::builder()
+            .build(EchoService::default())
+            .unwrap();
+        server.shutdown().await;
+        let (status, body) = call(&server.router(), "GET", "/ready", &[], None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "SHUTTING_DOWN");
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn test_ready_timeout() {
+        let (status, body) = probe(Probe::Hang, "/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "NOT_READY");
+        assert!(body["message"].as_str().unwrap().contains("timed out"));
     }
 }
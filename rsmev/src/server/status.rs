@@ -0,0 +1,185 @@
+//! Lifecycle of the requests of an entrypoint
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use uuid::Uuid;
+
+use super::api::{RequestStatus, StatusResponse};
+use super::client::{unix_ms, NodeId};
+
+/// Number of the confirmed requests remembered after they left the queue
+const FINISHED_HISTORY: usize = 10_000;
+
+struct Entry {
+    node_id: NodeId,
+    status: RequestStatus,
+    fault: bool,
+    updated: SystemTime,
+}
+
+#[derive(Default)]
+struct Statuses {
+    entries: HashMap<Uuid, Entry>,
+    // confirmed requests, the oldest are forgotten first
+    finished: VecDeque<Uuid>,
+}
+
+/// Status of every request from `sendrequest` to the confirmation
+#[derive(Default)]
+pub(crate) struct RequestTracker {
+    // requests waiting for a free worker
+    waiting: AtomicUsize,
+    statuses: Mutex<Statuses>,
+}
+
+impl RequestTracker {
+    pub fn queued(&self, key: Uuid, node_id: NodeId) {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+
+        let entry = Entry {
+            node_id,
+            status: RequestStatus::Queued,
+            fault: false,
+            updated: SystemTime::now(),
+        };
+        self.statuses.lock().unwrap().entries.insert(key, entry);
+    }
+
+    pub fn started(&self, key: Uuid) {
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        self.set(key, RequestStatus::Processing);
+    }
+
+    /// Response is queued for the node, `fault` if the service failed
+    pub fn handled(&self, key: Uuid, fault: bool) {
+        let mut statuses = self.statuses.lock().unwrap();
+        if let Some(entry) = statuses.entries.get_mut(&key) {
+            entry.fault = fault;
+        }
+        statuses.set(key, RequestStatus::ResponseReady);
+    }
+
+    /// Response replayed from the journal
+    pub fn restored(&self, key: Uuid, node_id: NodeId, status: RequestStatus, fault: bool) {
+        let entry = Entry {
+            node_id,
+            status,
+            fault,
+            updated: SystemTime::now(),
+        };
+        let mut statuses = self.statuses.lock().unwrap();
+        statuses.entries.insert(key, entry);
+        statuses.set(key, status);
+    }
+
+    /// A waiting fault is reported as `Failed` instead of `ResponseReady`
+    pub fn set(&self, key: Uuid, status: RequestStatus) {
+        self.statuses.lock().unwrap().set(key, status);
+    }
+
+    /// Forget the request, e.g. its response is removed by the admin
+    pub fn remove(&self, key: &Uuid) {
+        self.statuses.lock().unwrap().entries.remove(key);
+    }
+
+    pub fn get(&self, key: &Uuid) -> Option<StatusResponse> {
+        let statuses = self.statuses.lock().unwrap();
+        let entry = statuses.entries.get(key)?;
+
+        Some(StatusResponse {
+            request_id: *key,
+            node_id: entry.node_id.clone(),
+            status: entry.status,
+            fault: entry.fault,
+            updated_at_ms: unix_ms(entry.updated),
+        })
+    }
+
+    /// Requests waiting for a free worker
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+impl Statuses {
+    fn set(&mut self, key: Uuid, status: RequestStatus) {
+        let Some(entry) = self.entries.get_mut(&key) else {
+            return;
+        };
+        entry.status = match status {
+            RequestStatus::ResponseReady if entry.fault => RequestStatus::Failed,
+            status => status,
+        };
+        entry.updated = SystemTime::now();
+
+        if status != RequestStatus::Confirmed {
+            return;
+        }
+        if self.finished.len() >= FINISHED_HISTORY {
+            if let Some(oldest) = self.finished.pop_front() {
+                // unless it was requeued since then
+                if self
+                    .entries
+                    .get(&oldest)
+                    .is_some_and(|e| e.status == RequestStatus::Confirmed)
+                {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+        self.finished.push_back(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestTracker;
+    use crate::server::api::RequestStatus;
+
+    use uuid::Uuid;
+
+    #[test]
+    pub fn test_lifecycle() {
+        let tracker = RequestTracker::default();
+        let key = Uuid::new_v4();
+        let status = |tracker: &RequestTracker| tracker.get(&key).map(|s| s.status);
+
+        assert_eq!(status(&tracker), None);
+
+        tracker.queued(key, "master".to_string());
+        assert_eq!(status(&tracker), Some(RequestStatus::Queued));
+        assert_eq!(tracker.waiting(), 1);
+
+        tracker.started(key);
+        assert_eq!(status(&tracker), Some(RequestStatus::Processing));
+        assert_eq!(tracker.waiting(), 0);
+
+        tracker.handled(key, true);
+        assert_eq!(status(&tracker), Some(RequestStatus::Failed));
+
+        tracker.set(key, RequestStatus::Delivered);
+        tracker.set(key, RequestStatus::Confirmed);
+        let confirmed = tracker.get(&key).unwrap();
+        assert_eq!(confirmed.status, RequestStatus::Confirmed);
+        assert!(confirmed.fault);
+        assert_eq!(confirmed.node_id, "master");
+
+        tracker.restored(
+            key,
+            "master".to_string(),
+            RequestStatus::ResponseReady,
+            true,
+        );
+        assert_eq!(status(&tracker), Some(RequestStatus::Failed));
+
+        tracker.remove(&key);
+        assert_eq!(status(&tracker), None);
+    }
+}
@@ -0,0 +1,367 @@
+//! HTTPS with optional client certificate verification, requires the `tls` feature
+
+use std::{
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::{pin, Pin},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use openssl::{
+    nid::Nid,
+    ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode},
+    x509::{store::X509StoreBuilder, X509NameRef, X509Ref},
+};
+use tokio::{net::TcpListener, sync::watch};
+use tokio_openssl::SslStream;
+use tower_service::Service as _;
+
+use crate::accept::AcceptBackoff;
+use crate::crypto::{dir_certificates, read_certificates, Error};
+
+/// Time a client has to finish the tls handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct TlsConfig {
+    /// PEM certificate chain of the server
+    pub cert: PathBuf,
+    /// PEM private key of the server
+    pub key: PathBuf,
+    /// Certificate file or directory of the CAs the client certificates are
+    /// verified against, a client certificate is required when it is set
+    pub client_ca: Option<PathBuf>,
+    /// Use common name of the client certificate as the node id, a
+    /// `node_id` header sent by the client is replaced
+    pub node_id_from_subject: bool,
+}
+
+impl TlsConfig {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: None,
+            node_id_from_subject: false,
+        }
+    }
+}
+
+/// Verified client certificate, available to the handlers as a request extension
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    /// Subject in the `CN=node,O=org` form
+    pub subject: String,
+    pub common_name: Option<String>,
+}
+
+impl ClientCertificate {
+    fn new(cert: &X509Ref) -> Self {
+        let name = cert.subject_name();
+
+        Self {
+            subject: subject(name),
+            common_name: name
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .and_then(|e| e.data().to_string().ok()),
+        }
+    }
+}
+
+fn subject(name: &X509NameRef) -> String {
+    name.entries()
+        .filter_map(|e| {
+            let key = e.object().nid().short_name().ok()?;
+            let value = e.data().to_string().ok()?;
+            Some(format!("{key}={value}"))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn acceptor(config: &TlsConfig) -> Result<SslAcceptor, Error> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    builder.set_certificate_chain_file(&config.cert)?;
+    builder.set_private_key_file(&config.key, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+
+    if let Some(path) = &config.client_ca {
+        // a directory may hold other files, e.g. keys or serials, they are skipped
+        let certs = if path.is_dir() {
+            dir_certificates(path)?
+        } else {
+            read_certificates(path)?
+        };
+
+        let mut store = X509StoreBuilder::new()?;
+        for cert in certs {
+            builder.add_client_ca(&cert)?;
+            store.add_cert(cert)?;
+        }
+
+        builder.set_verify_cert_store(store.build())?;
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    Ok(builder.build())
+}
+
+/// Serve until `signal` resolves, then wait for the open connections
+pub(crate) async fn serve(
+    listener: TcpListener,
+    routes: Router,
+    config: TlsConfig,
+    node_id_header: HeaderName,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let acceptor = Arc::new(acceptor(&config).map_err(io::Error::other)?);
+    let node_id_from_subject = config.node_id_from_subject;
+
+    // every connection holds a receiver, the sender is closed when all of them are done
+    let (close_tx, close_rx) = watch::channel(());
+    let mut signal = pin!(signal);
+    let mut backoff = AcceptBackoff::default();
+
+    loop {
+        let (stream, remote) = tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok(connection) => {
+                    backoff.reset();
+                    connection
+                }
+                Err(e) => match backoff.next(&e) {
+                    Some(delay) => tokio::select! {
+                        _ = tokio::time::sleep(delay) => continue,
+                        _ = &mut signal => break,
+                    },
+                    None => continue,
+                },
+            },
+            _ = &mut signal => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let routes = routes.clone();
+        let node_id_header = node_id_header.clone();
+        let mut close_rx = close_rx.clone();
+        tokio::spawn(async move {
+            // a client that never sends its hello must not hold the shutdown
+            let stream = tokio::select! {
+                result = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&acceptor, stream)) => {
+                    match result {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            tracing::debug!(%remote, error = %e, "tls handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            tracing::debug!(%remote, "tls handshake timed out");
+                            return;
+                        }
+                    }
+                }
+                _ = close_rx.changed() => return,
+            };
+
+            let certificate = stream
+                .ssl()
+                .peer_certificate()
+                .map(|c| ClientCertificate::new(&c));
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                if let Some(certificate) = &certificate {
+                    let node_id_header = node_id_from_subject.then_some(&node_id_header);
+                    with_certificate(&mut request, certificate, node_id_header);
+                }
+
+                routes.clone().call(request)
+            });
+
+            let connection = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service);
+            let mut connection = pin!(connection);
+
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = close_rx.changed() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!(%remote, error = %e, "connection closed");
+            }
+        });
+    }
+
+    drop(listener);
+    drop(close_rx);
+    let _ = close_tx.send(());
+    close_tx.closed().await;
+
+    Ok(())
+}
+
+async fn handshake(
+    acceptor: &SslAcceptor,
+    stream: tokio::net::TcpStream,
+) -> Result<SslStream<tokio::net::TcpStream>, Box<dyn std::error::Error>> {
+    let mut stream = SslStream::new(Ssl::new(acceptor.context())?, stream)?;
+    Pin::new(&mut stream).accept().await?;
+
+    Ok(stream)
+}
+
+/// Attach the certificate to the request, the common name replaces the
+/// node id when `node_id_header` is given, so a client cannot act as another node
+fn with_certificate<B>(
+    request: &mut Request<B>,
+    certificate: &ClientCertificate,
+    node_id_header: Option<&HeaderName>,
+) {
+    let node_id = certificate
+        .common_name
+        .as_deref()
+        .and_then(|cn| HeaderValue::from_str(cn).ok());
+
+    if let (Some(name), Some(node_id)) = (node_id_header, node_id) {
+        request.headers_mut().insert(name, node_id);
+    }
+    request.extensions_mut().insert(certificate.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_certificate, ClientCertificate, TlsConfig};
+    use crate::crypto::pkcs7::tests::test_identity;
+
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    use axum::{routing::get, Extension, Router};
+    use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn test_client_certificate() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (cert, key) = test_identity();
+        std::fs::write(dir.join("cert.pem"), cert.to_pem().unwrap()).unwrap();
+        std::fs::write(dir.join("key.pem"), key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        // the key and the readme next to the certificate are skipped
+        std::fs::write(dir.join("README"), "client CAs").unwrap();
+        let config = TlsConfig {
+            client_ca: Some(dir.clone()),
+            ..TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem"))
+        };
+        let routes = Router::new().route(
+            "/",
+            get(|Extension(c): Extension<ClientCertificate>| async move { c.subject }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(super::serve(
+            listener,
+            routes,
+            config,
+            axum::http::HeaderName::from_static("node_id"),
+            std::future::pending(),
+        ));
+
+        let response = tokio::task::spawn_blocking(move || {
+            let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            connector.set_certificate(&cert).unwrap();
+            connector.set_private_key(&key).unwrap();
+
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            let mut stream = connector.build().connect("localhost", stream).unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            response
+        })
+        .await
+        .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("CN=rsmev test"), "{response}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_stalled_handshake() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (cert, key) = test_identity();
+        std::fs::write(dir.join("cert.pem"), cert.to_pem().unwrap()).unwrap();
+        std::fs::write(dir.join("key.pem"), key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(super::serve(
+            listener,
+            Router::new(),
+            TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem")),
+            axum::http::HeaderName::from_static("node_id"),
+            async move {
+                let _ = stop_rx.await;
+            },
+        ));
+
+        // connected, but the client hello is never sent
+        let _stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        stop_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(2), server)
+            .await
+            .expect("shutdown waits for the stalled handshake")
+            .unwrap()
+            .unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_node_id_from_subject() {
+        let certificate = ClientCertificate {
+            subject: "CN=node-a".to_string(),
+            common_name: Some("node-a".to_string()),
+        };
+        let header = axum::http::HeaderName::from_static("node_id");
+
+        let mut request = axum::http::Request::builder()
+            .header("node_id", "node-b")
+            .body(())
+            .unwrap();
+        with_certificate(&mut request, &certificate, Some(&header));
+        assert_eq!(request.headers().get_all("node_id").iter().count(), 1);
+        assert_eq!(request.headers()["node_id"], "node-a");
+
+        // the header is kept when the node id is not taken from the subject
+        let mut request = axum::http::Request::builder()
+            .header("node_id", "node-b")
+            .body(())
+            .unwrap();
+        with_certificate(&mut request, &certificate, None);
+        assert_eq!(request.headers()["node_id"], "node-b");
+    }
+}
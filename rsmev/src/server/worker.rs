@@ -0,0 +1,273 @@
+//! Handling of the requests of an entrypoint by a pool of tasks
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+};
+
+use tokio::{sync::mpsc, task::JoinSet};
+use uuid::Uuid;
+
+use super::body::Body;
+use super::client::NodeId;
+use super::fault::{Fault, FaultCode};
+use super::handler_service::HandlerService;
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+use super::status::RequestTracker;
+use crate::service::{Message, Service};
+
+pub(crate) type Task<R> = (NodeId, Uuid, Message<R>);
+
+/// Limits of the requests handled at once
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WorkerConfig {
+    pub concurrency: usize,
+    pub node_concurrency: Option<usize>,
+    pub preserve_node_order: bool,
+    /// Requests waiting for a free worker, including the one in the channel
+    pub buffer: usize,
+}
+
+struct NodeState<R> {
+    pending: VecDeque<(u64, Uuid, Message<R>)>,
+    running: usize,
+    // sequence of the next received request
+    next_seq: u64,
+    // sequence of the next response to deliver, when the order is preserved
+    next_delivery: u64,
+    handled: BTreeMap<u64, (Uuid, Result<Body, Body>)>,
+}
+
+impl<R> Default for NodeState<R> {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            running: 0,
+            next_seq: 0,
+            next_delivery: 0,
+            handled: BTreeMap::new(),
+        }
+    }
+}
+
+/// Handle the requests of `rx` until it is closed and drained, every
+/// response is passed to `deliver`, `Err` with a fault of the service
+pub(crate) async fn run<S: Service>(
+    entrypoint_id: Uuid,
+    service: Arc<HandlerService<S>>,
+    config: WorkerConfig,
+    tracker: Arc<RequestTracker>,
+    #[cfg(feature = "metrics")] metrics: Arc<Metrics>,
+    mut rx: mpsc::Receiver<Task<S::Request>>,
+    mut deliver: impl FnMut(NodeId, Uuid, Result<Body, Body>),
+) {
+    let concurrency = config.concurrency.max(1);
+    let node_concurrency = config.node_concurrency.unwrap_or(concurrency).max(1);
+
+    let mut nodes: HashMap<NodeId, NodeState<S::Request>> = HashMap::new();
+    // nodes with pending requests, served round-robin
+    let mut ready: VecDeque<NodeId> = VecDeque::new();
+    let mut buffered = 0;
+    let mut running = 0;
+    let mut closed = false;
+    let mut tasks = JoinSet::new();
+
+    loop {
+        // start as many requests as the limits allow
+        let mut skipped = 0;
+        while running < concurrency && skipped < ready.len() {
+            let Some(node_id) = ready.pop_front() else {
+                break;
+            };
+            let node = nodes.get_mut(&node_id).expect("ready node exists");
+
+            if node.running >= node_concurrency {
+                ready.push_back(node_id);
+                skipped += 1;
+                continue;
+            }
+
+            let (seq, key, message) = node.pending.pop_front().expect("ready node has requests");
+            node.running += 1;
+            running += 1;
+            buffered -= 1;
+            tracker.started(key);
+            skipped = 0;
+
+            tasks.spawn(handle(
+                entrypoint_id,
+                service.clone(),
+                #[cfg(feature = "metrics")]
+                metrics.clone(),
+                node_id.clone(),
+                seq,
+                key,
+                message,
+            ));
+            if !node.pending.is_empty() {
+                ready.push_back(node_id);
+            }
+        }
+
+        // the channel holds one more waiting request, a request that starts
+        // at once is taken even when nothing else may wait
+        let limit = config.buffer.saturating_sub(1);
+        let receive = !closed && (buffered < limit || (buffered == 0 && running < concurrency));
+        tokio::select! {
+            task = rx.recv(), if receive => {
+                let Some((node_id, key, message)) = task else {
+                    closed = true;
+                    continue;
+                };
+
+                let node = nodes.entry(node_id.clone()).or_default();
+                if node.pending.is_empty() {
+                    ready.push_back(node_id);
+                }
+                node.pending.push_back((node.next_seq, key, message));
+                node.next_seq += 1;
+                buffered += 1;
+            }
+            Some(result) = tasks.join_next() => {
+                let (node_id, seq, key, response) = result.expect("handler task is not cancelled");
+                running -= 1;
+
+                // a node is in `ready` while it has pending requests
+                let node = nodes.get_mut(&node_id).expect("running node exists");
+                node.running -= 1;
+
+                if !config.preserve_node_order {
+                    deliver(node_id, key, response);
+                    continue;
+                }
+
+                node.handled.insert(seq, (key, response));
+                while let Some((key, response)) = node.handled.remove(&node.next_delivery) {
+                    node.next_delivery += 1;
+                    deliver(node_id.clone(), key, response);
+                }
+            }
+            else => break,
+        }
+    }
+}
+
+async fn handle<S: Service>(
+    entrypoint_id: Uuid,
+    service: Arc<HandlerService<S>>,
+    #[cfg(feature = "metrics")] metrics: Arc<Metrics>,
+    node_id: NodeId,
+    seq: u64,
+    key: Uuid,
+    message: Message<S::Request>,
+) -> (NodeId, u64, Uuid, Result<Body, Body>) {
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+
+    // a panic of the service is answered with a fault, so the node is not left waiting
+    let handler = service.clone();
+    let response = match tokio::spawn(async move { handler.handle(key, message).await }).await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!(%entrypoint_id, request_id = %key, error = %e, "service panicked");
+            Err(service.fault(Fault::new(FaultCode::Service, "service panicked", key)))
+        }
+    };
+
+    #[cfg(feature = "metrics")]
+    metrics.handled(entrypoint_id, started.elapsed(), response.is_err());
+
+    (node_id, seq, key, response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, WorkerConfig};
+    use crate::file_store::MemoryFileStore;
+    use crate::server::handler_service::HandlerService;
+    use crate::service::{Message, Service};
+
+    use crate::server::status::RequestTracker;
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct Delay {
+        #[serde(rename = "Ms")]
+        ms: u64,
+    }
+
+    struct SleepService;
+
+    impl Service for SleepService {
+        type Request = Delay;
+        type Response = Delay;
+        type Error = std::io::Error;
+
+        async fn handle(&self, content: Message<Delay>) -> Result<Message<Delay>, Self::Error> {
+            tokio::time::sleep(Duration::from_millis(content.content.ms)).await;
+            Ok(content)
+        }
+    }
+
+    async fn handled_order(preserve_node_order: bool) -> Vec<String> {
+        let service = Arc::new(HandlerService::new(
+            SleepService,
+            Arc::new(MemoryFileStore::new()),
+        ));
+        let config = WorkerConfig {
+            concurrency: 2,
+            node_concurrency: None,
+            preserve_node_order,
+            buffer: 16,
+        };
+
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let worker = tokio::spawn(run(
+            Uuid::new_v4(),
+            service,
+            config,
+            Arc::new(RequestTracker::default()),
+            #[cfg(feature = "metrics")]
+            Arc::new(crate::server::metrics::Metrics::new()),
+            rx,
+            {
+                let delivered = delivered.clone();
+                move |_, _, response: Result<crate::server::body::Body, _>| {
+                    let body = response.unwrap();
+                    delivered.lock().unwrap().push(body.xml.decode().unwrap())
+                }
+            },
+        ));
+
+        for ms in [200, 10] {
+            let message = Message {
+                content: Delay { ms },
+                files: Vec::new(),
+            };
+            tx.send(("master".to_string(), Uuid::new_v4(), message))
+                .await
+                .unwrap();
+        }
+        drop(tx);
+        worker.await.unwrap();
+
+        let delivered = delivered.lock().unwrap().clone();
+        delivered
+    }
+
+    #[tokio::test]
+    pub async fn test_concurrency() {
+        // the short request overtakes the long one only if both run at once
+        let unordered = handled_order(false).await;
+        assert!(unordered[0].contains("<Ms>10</Ms>"), "{unordered:?}");
+
+        let ordered = handled_order(true).await;
+        assert!(ordered[0].contains("<Ms>200</Ms>"), "{ordered:?}");
+        assert!(ordered[1].contains("<Ms>10</Ms>"), "{ordered:?}");
+    }
+}
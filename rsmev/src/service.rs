@@ -22,4 +22,14 @@ pub trait Service: Send + Sync + 'static {
         &self,
         content: Message<Self::Request>,
     ) -> impl Future<Output = std::result::Result<Message<Self::Response>, Self::Error>> + Send;
+
+    /// XSD schemas the inbound xml is validated against, requires the `xsd` feature
+    fn schemas(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Whether the backing resources are reachable, reported by `/ready`
+    fn ready(&self) -> impl Future<Output = std::result::Result<(), Self::Error>> + Send {
+        std::future::ready(Ok(()))
+    }
 }
@@ -0,0 +1,379 @@
+//! XSD validation of the inbound xml, backed by libxml2. Requires the `xsd` feature.
+//!
+//! Every schema is bound to its `targetNamespace`, a document is validated
+//! against the schema of its root element namespace.
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+};
+
+use quick_xml::{events::Event, name::ResolveResult, NsReader, Reader};
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, Vec<String>),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Read(path, e) => write!(f, "failed to read `{}`: {e}", path.display()),
+            SchemaError::Parse(path, errors) => {
+                write!(
+                    f,
+                    "invalid schema `{}`: {}",
+                    path.display(),
+                    errors.join("; ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+struct Schema {
+    target_namespace: Option<String>,
+    ptr: *mut ffi::XmlSchema,
+}
+
+// SAFETY: the schema is owned by this value only, moving it to another thread
+// is fine; concurrent access is guarded by the `Mutex` in `SchemaSet`
+unsafe impl Send for Schema {}
+
+impl Drop for Schema {
+    fn drop(&mut self) {
+        unsafe { ffi::xmlSchemaFree(self.ptr) }
+    }
+}
+
+#[derive(Default)]
+pub struct SchemaSet {
+    schemas: Vec<(Option<String>, Mutex<Schema>)>,
+}
+
+impl SchemaSet {
+    pub fn load(paths: &[PathBuf]) -> Result<Self, SchemaError> {
+        init();
+
+        let schemas = paths
+            .iter()
+            .map(|path| load_schema(path).map(|s| (s.target_namespace.clone(), Mutex::new(s))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { schemas })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Validate the document, errors are the libxml2 messages
+    pub fn validate(&self, xml: &str) -> Result<(), Vec<String>> {
+        if self.schemas.is_empty() {
+            return Ok(());
+        }
+
+        let namespace = root_namespace(xml).map_err(|e| vec![e.to_string()])?;
+        let (_, schema) = self
+            .schemas
+            .iter()
+            .find(|(target_namespace, _)| *target_namespace == namespace)
+            .ok_or_else(|| {
+                vec![format!(
+                    "no schema for the root element namespace `{}`",
+                    namespace.as_deref().unwrap_or_default()
+                )]
+            })?;
+
+        let schema = schema.lock().unwrap_or_else(|e| e.into_inner());
+        validate(&schema, xml)
+    }
+}
+
+static INIT: Once = Once::new();
+
+fn init() {
+    INIT.call_once(|| unsafe { ffi::xmlInitParser() });
+}
+
+fn load_schema(path: &Path) -> Result<Schema, SchemaError> {
+    let content = std::fs::read_to_string(path).map_err(|e| SchemaError::Read(path.into(), e))?;
+    let target_namespace = target_namespace(&content)
+        .map_err(|e| SchemaError::Parse(path.into(), vec![e.to_string()]))?;
+
+    // the schema is parsed from the file to resolve relative `xs:include`s
+    let url = CString::new(path.to_string_lossy().as_bytes())
+        .map_err(|e| SchemaError::Parse(path.into(), vec![e.to_string()]))?;
+    let mut errors = Vec::<String>::new();
+
+    let ptr = unsafe {
+        let ctxt = ffi::xmlSchemaNewParserCtxt(url.as_ptr());
+        if ctxt.is_null() {
+            return Err(SchemaError::Parse(
+                path.into(),
+                vec!["out of memory".into()],
+            ));
+        }
+
+        ffi::xmlSchemaSetParserStructuredErrors(
+            ctxt,
+            collect_error,
+            &mut errors as *mut Vec<String> as *mut c_void,
+        );
+        let schema = ffi::xmlSchemaParse(ctxt);
+        ffi::xmlSchemaFreeParserCtxt(ctxt);
+
+        schema
+    };
+
+    if ptr.is_null() {
+        Err(SchemaError::Parse(path.into(), errors))
+    } else {
+        Ok(Schema {
+            target_namespace,
+            ptr,
+        })
+    }
+}
+
+fn validate(schema: &Schema, xml: &str) -> Result<(), Vec<String>> {
+    let size = c_int::try_from(xml.len()).map_err(|_| vec!["document is too large".into()])?;
+    let mut errors = Vec::<String>::new();
+
+    unsafe {
+        // errors are read from the parser context, not the global last error
+        let parser = ffi::xmlNewParserCtxt();
+        if parser.is_null() {
+            return Err(vec!["out of memory".into()]);
+        }
+
+        let doc = ffi::xmlCtxtReadMemory(
+            parser,
+            xml.as_ptr() as *const c_char,
+            size,
+            c"request.xml".as_ptr(),
+            std::ptr::null(),
+            ffi::XML_PARSE_NONET | ffi::XML_PARSE_NOERROR | ffi::XML_PARSE_NOWARNING,
+        );
+        if doc.is_null() {
+            collect_error(
+                &mut errors as *mut Vec<String> as *mut c_void,
+                ffi::xmlCtxtGetLastError(parser as *mut c_void),
+            );
+            ffi::xmlFreeParserCtxt(parser);
+            if errors.is_empty() {
+                errors.push("document is not well-formed".to_string());
+            }
+            return Err(errors);
+        }
+        ffi::xmlFreeParserCtxt(parser);
+
+        let ctxt = ffi::xmlSchemaNewValidCtxt(schema.ptr);
+        if ctxt.is_null() {
+            ffi::xmlFreeDoc(doc);
+            return Err(vec!["out of memory".into()]);
+        }
+
+        ffi::xmlSchemaSetValidStructuredErrors(
+            ctxt,
+            collect_error,
+            &mut errors as *mut Vec<String> as *mut c_void,
+        );
+        let result = ffi::xmlSchemaValidateDoc(ctxt, doc);
+
+        ffi::xmlSchemaFreeValidCtxt(ctxt);
+        ffi::xmlFreeDoc(doc);
+
+        match result {
+            0 => Ok(()),
+            _ if errors.is_empty() => Err(vec![format!("validation failed with code {result}")]),
+            _ => Err(errors),
+        }
+    }
+}
+
+unsafe extern "C" fn collect_error(ctx: *mut c_void, error: *mut ffi::XmlError) {
+    if ctx.is_null() || error.is_null() {
+        return;
+    }
+
+    let errors = &mut *(ctx as *mut Vec<String>);
+    let error = &*error;
+
+    let message = if error.message.is_null() {
+        "unknown error".to_string()
+    } else {
+        CStr::from_ptr(error.message)
+            .to_string_lossy()
+            .trim_end()
+            .to_string()
+    };
+
+    if error.line > 0 {
+        errors.push(format!("line {}: {message}", error.line));
+    } else {
+        errors.push(message);
+    }
+}
+
+fn target_namespace(xsd: &str) -> Result<Option<String>, quick_xml::Error> {
+    let mut reader = Reader::from_str(xsd);
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => {
+                let namespace = e
+                    .try_get_attribute("targetNamespace")?
+                    .map(|a| a.unescape_value().map(|v| v.to_string()))
+                    .transpose()?;
+
+                return Ok(namespace.filter(|n| !n.is_empty()));
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+fn root_namespace(xml: &str) -> Result<Option<String>, quick_xml::Error> {
+    let mut reader = NsReader::from_str(xml);
+
+    loop {
+        match reader.read_resolved_event()? {
+            (ResolveResult::Bound(ns), Event::Start(_) | Event::Empty(_)) => {
+                return Ok(Some(String::from_utf8_lossy(ns.as_ref()).to_string()))
+            }
+            (_, Event::Start(_) | Event::Empty(_)) | (_, Event::Eof) => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+mod ffi {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub const XML_PARSE_NOERROR: c_int = 1 << 5;
+    pub const XML_PARSE_NOWARNING: c_int = 1 << 6;
+    pub const XML_PARSE_NONET: c_int = 1 << 11;
+
+    /// `xmlError` of `libxml/xmlerror.h`, the layout is unchanged since
+    /// libxml2 2.6, the version is checked by `build.rs`
+    #[repr(C)]
+    pub struct XmlError {
+        pub domain: c_int,
+        pub code: c_int,
+        pub message: *mut c_char,
+        pub level: c_int,
+        pub file: *mut c_char,
+        pub line: c_int,
+        pub str1: *mut c_char,
+        pub str2: *mut c_char,
+        pub str3: *mut c_char,
+        pub int1: c_int,
+        pub int2: c_int,
+        pub ctxt: *mut c_void,
+        pub node: *mut c_void,
+    }
+
+    pub enum XmlDoc {}
+    pub enum XmlParserCtxt {}
+    pub enum XmlSchema {}
+    pub enum XmlSchemaParserCtxt {}
+    pub enum XmlSchemaValidCtxt {}
+
+    pub type XmlStructuredErrorFunc = unsafe extern "C" fn(*mut c_void, *mut XmlError);
+
+    // linked by `build.rs` through pkg-config
+    extern "C" {
+        pub fn xmlInitParser();
+
+        pub fn xmlNewParserCtxt() -> *mut XmlParserCtxt;
+        pub fn xmlFreeParserCtxt(ctxt: *mut XmlParserCtxt);
+        pub fn xmlCtxtGetLastError(ctx: *mut c_void) -> *mut XmlError;
+        pub fn xmlCtxtReadMemory(
+            ctxt: *mut XmlParserCtxt,
+            buffer: *const c_char,
+            size: c_int,
+            URL: *const c_char,
+            encoding: *const c_char,
+            options: c_int,
+        ) -> *mut XmlDoc;
+        pub fn xmlFreeDoc(doc: *mut XmlDoc);
+
+        pub fn xmlSchemaNewParserCtxt(URL: *const c_char) -> *mut XmlSchemaParserCtxt;
+        pub fn xmlSchemaSetParserStructuredErrors(
+            ctxt: *mut XmlSchemaParserCtxt,
+            serror: XmlStructuredErrorFunc,
+            ctx: *mut c_void,
+        );
+        pub fn xmlSchemaParse(ctxt: *mut XmlSchemaParserCtxt) -> *mut XmlSchema;
+        pub fn xmlSchemaFreeParserCtxt(ctxt: *mut XmlSchemaParserCtxt);
+        pub fn xmlSchemaFree(schema: *mut XmlSchema);
+
+        pub fn xmlSchemaNewValidCtxt(schema: *mut XmlSchema) -> *mut XmlSchemaValidCtxt;
+        pub fn xmlSchemaSetValidStructuredErrors(
+            ctxt: *mut XmlSchemaValidCtxt,
+            serror: XmlStructuredErrorFunc,
+            ctx: *mut c_void,
+        );
+        pub fn xmlSchemaValidateDoc(ctxt: *mut XmlSchemaValidCtxt, doc: *mut XmlDoc) -> c_int;
+        pub fn xmlSchemaFreeValidCtxt(ctxt: *mut XmlSchemaValidCtxt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SchemaSet;
+
+    const SCHEMA: &str = r#"<?xml version="1.0"?>
+<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" targetNamespace="urn:pos" elementFormDefault="qualified">
+  <xs:element name="AppealListRequest">
+    <xs:complexType>
+      <xs:sequence>
+        <xs:element name="ClientId" type="xs:string"/>
+      </xs:sequence>
+    </xs:complexType>
+  </xs:element>
+</xs:schema>
+"#;
+
+    #[test]
+    pub fn test_validate() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pos.xsd");
+        std::fs::write(&path, SCHEMA).unwrap();
+
+        let schemas = SchemaSet::load(&[path]).unwrap();
+        assert!(schemas
+            .validate(
+                r#"<AppealListRequest xmlns="urn:pos"><ClientId>1</ClientId></AppealListRequest>"#
+            )
+            .is_ok());
+
+        let errors = schemas
+            .validate(
+                r#"<AppealListRequest xmlns="urn:pos"><Client>1</Client></AppealListRequest>"#,
+            )
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Client"), "{errors:?}");
+
+        let errors = schemas
+            .validate("<AppealListRequest><ClientId>1</ClientId></AppealListRequest>")
+            .unwrap_err();
+        assert!(errors[0].contains("no schema"), "{errors:?}");
+
+        let errors = schemas
+            .validate(r#"<AppealListRequest xmlns="urn:pos"><ClientId>1</AppealListRequest>"#)
+            .unwrap_err();
+        assert!(errors[0].starts_with("line 1:"), "{errors:?}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}